@@ -1,5 +1,21 @@
+pub mod addresses;
+pub mod cat;
+pub mod disable;
+pub mod enable;
+pub mod find;
+pub mod get;
+pub mod info;
 pub mod list;
+pub mod ls;
+pub mod mirror;
+pub mod mkdir;
+pub mod mv;
+pub mod put;
 pub mod query;
+pub mod rm;
 pub mod share;
 pub mod shut_down;
+pub mod trust;
 pub mod unshare;
+pub mod untrust;
+pub mod watch;
@@ -0,0 +1,29 @@
+use crate::utils;
+use anyhow::Result;
+use url::{Host, Url};
+
+pub async fn run() -> Result<()> {
+    let (daemon, join) = utils::connect_daemon().await?;
+
+    let urls = daemon.listen_addrs().await??;
+
+    for url in urls {
+        if is_wildcard(&url) {
+            println!("{url} (wildcard; connect via any address of this host)");
+        } else {
+            println!("{url}");
+        }
+    }
+
+    daemon.client().shutdown();
+    join.await??;
+    Ok(())
+}
+
+fn is_wildcard(url: &str) -> bool {
+    match Url::parse(url).as_ref().map(Url::host) {
+        Ok(Some(Host::Ipv4(ip))) => ip.is_unspecified(),
+        Ok(Some(Host::Ipv6(ip))) => ip.is_unspecified(),
+        _ => false,
+    }
+}
@@ -0,0 +1,87 @@
+use crate::schemas::{features, WilyReadArgs, WilyReadError};
+use crate::utils;
+use anyhow::{anyhow, Result};
+use tokio::io::{self, AsyncWriteExt};
+use url::Url;
+
+const CHANNEL_CAPACITY: u32 = 16;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// URL of a shared file.
+    url: Url,
+
+    /// Keep printing data as the file grows, like `tail -f`.
+    ///
+    /// Truncated files are printed again from the start and rotated files are followed to their
+    /// replacement. Stop with Ctrl-C.
+    #[clap(short, long)]
+    follow: bool,
+}
+
+pub async fn run(args: Args) -> Result<()> {
+    let required: &[&str] = if args.follow {
+        &[features::FOLLOW]
+    } else {
+        &[]
+    };
+
+    let (wily, join) = utils::connect_wily(&args.url, required).await?;
+
+    let res = async {
+        let (sender, receiver) = wily
+            .client()
+            .create_channel_with_claimed_receiver(CHANNEL_CAPACITY)
+            .await?;
+
+        let res = wily
+            .read(&WilyReadArgs {
+                path: utils::url_path(&args.url)?,
+                sender: sender.unbind(),
+                offset: None,
+                length: None,
+                if_unchanged: None,
+                follow: Some(args.follow),
+            })
+            .await?;
+
+        let read = match res {
+            Ok(read) => read,
+            Err(WilyReadError::FileNotFound) => return Err(anyhow!("`{}` not found", args.url)),
+            Err(WilyReadError::NotAFile) => return Err(anyhow!("`{}` is not a file", args.url)),
+            Err(WilyReadError::PermissionDenied) => {
+                return Err(anyhow!("permission denied for `{}`", args.url))
+            }
+
+            // Neither can happen without a range or version, but the daemon may be remote.
+            Err(WilyReadError::Modified) | Err(WilyReadError::InvalidRange) => {
+                return Err(anyhow!("unexpected reply when reading `{}`", args.url))
+            }
+        };
+
+        let mut receiver = receiver.established().await?;
+        let mut stdout = io::stdout();
+        let mut received = 0;
+
+        while let Some(chunk) = receiver.next_item().await? {
+            stdout.write_all(&chunk).await?;
+            stdout.flush().await?;
+            received += chunk.len() as u64;
+        }
+
+        if !args.follow && (received != read.size) {
+            return Err(anyhow!(
+                "`{}` is incomplete ({received} of {} bytes)",
+                args.url,
+                read.size
+            ));
+        }
+
+        Ok(())
+    }
+    .await;
+
+    wily.client().shutdown();
+    join.await??;
+    res
+}
@@ -0,0 +1,37 @@
+use crate::schemas::{DaemonDisableArgs, DaemonDisableError};
+use crate::utils;
+use anyhow::{anyhow, Result};
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// Name of the share to disable.
+    name: String,
+}
+
+pub async fn run(args: Args) -> Result<()> {
+    let (daemon, join) = utils::connect_daemon().await?;
+
+    let res = daemon
+        .disable(&DaemonDisableArgs {
+            name: args.name.clone(),
+        })
+        .await?;
+
+    let res = match res {
+        Ok(share) => {
+            println!("Share `{}` disabled:", args.name);
+            println!();
+            utils::print_share(&share);
+
+            Ok(())
+        }
+
+        Err(DaemonDisableError::UnknownShare) => Err(anyhow!("unknown share `{}`", args.name)),
+
+        Err(DaemonDisableError::PersistFailed) => Err(anyhow!("failed to save persisted shares")),
+    };
+
+    daemon.client().shutdown();
+    join.await??;
+    res
+}
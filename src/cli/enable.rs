@@ -0,0 +1,37 @@
+use crate::schemas::{DaemonEnableArgs, DaemonEnableError};
+use crate::utils;
+use anyhow::{anyhow, Result};
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// Name of the share to enable.
+    name: String,
+}
+
+pub async fn run(args: Args) -> Result<()> {
+    let (daemon, join) = utils::connect_daemon().await?;
+
+    let res = daemon
+        .enable(&DaemonEnableArgs {
+            name: args.name.clone(),
+        })
+        .await?;
+
+    let res = match res {
+        Ok(share) => {
+            println!("Share `{}` enabled:", args.name);
+            println!();
+            utils::print_share(&share);
+
+            Ok(())
+        }
+
+        Err(DaemonEnableError::UnknownShare) => Err(anyhow!("unknown share `{}`", args.name)),
+
+        Err(DaemonEnableError::PersistFailed) => Err(anyhow!("failed to save persisted shares")),
+    };
+
+    daemon.client().shutdown();
+    join.await??;
+    res
+}
@@ -0,0 +1,155 @@
+use crate::schemas::{
+    features, FileType, PatternType, SearchMatch, WilySearchArgs, WilySearchError,
+};
+use crate::utils;
+use anyhow::{anyhow, Context, Error, Result};
+use chrono::Utc;
+use clap::ValueEnum;
+use std::time::Duration;
+use url::Url;
+
+const CHANNEL_CAPACITY: u32 = 16;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// URL of a shared directory.
+    url: Url,
+
+    /// Glob pattern that file names must match, e.g. `*.log`.
+    ///
+    /// Patterns containing a `/` are matched against the path relative to the searched directory
+    /// instead.
+    pattern: String,
+
+    /// Treat the pattern as a plain substring instead of a glob.
+    #[clap(short, long)]
+    substring: bool,
+
+    /// Match the pattern case-insensitively.
+    #[clap(short, long)]
+    ignore_case: bool,
+
+    /// Only find entries of this type.
+    #[clap(short = 't', long = "type", value_enum)]
+    file_type: Option<Type>,
+
+    /// Only find files at least this large, e.g. `10M`.
+    #[clap(long, value_parser = utils::parse_size)]
+    min_size: Option<u64>,
+
+    /// Only find files at most this large, e.g. `1G`.
+    #[clap(long, value_parser = utils::parse_size)]
+    max_size: Option<u64>,
+
+    /// Only find entries modified within this duration, e.g. `2h` or `7d`.
+    #[clap(long, value_parser = utils::parse_duration)]
+    newer_than: Option<Duration>,
+
+    /// Only find entries modified before this duration, e.g. `30d`.
+    #[clap(long, value_parser = utils::parse_duration)]
+    older_than: Option<Duration>,
+
+    /// Maximum depth to descend to. Entries directly inside the directory have depth 1.
+    #[clap(short = 'd', long)]
+    max_depth: Option<u32>,
+
+    /// Maximum number of results.
+    #[clap(short = 'n', long)]
+    limit: Option<u32>,
+}
+
+pub async fn run(args: Args) -> Result<()> {
+    let (wily, join) = utils::connect_wily(&args.url, &[features::SEARCH]).await?;
+
+    let res = async {
+        let (sender, receiver) = wily
+            .client()
+            .create_channel_with_claimed_receiver(CHANNEL_CAPACITY)
+            .await?;
+
+        let res = wily
+            .search(&WilySearchArgs {
+                path: utils::url_path(&args.url)?,
+                pattern: args.pattern.clone(),
+                sender: sender.unbind(),
+                pattern_type: Some(if args.substring {
+                    PatternType::Substring
+                } else {
+                    PatternType::Glob
+                }),
+                ignore_case: Some(args.ignore_case),
+                file_type: args.file_type.map(Into::into),
+                min_size: args.min_size,
+                max_size: args.max_size,
+                modified_after_unix_ms: args.newer_than.map(unix_ms_ago).transpose()?,
+                modified_before_unix_ms: args.older_than.map(unix_ms_ago).transpose()?,
+                max_depth: args.max_depth,
+                limit: args.limit,
+            })
+            .await?;
+
+        match res {
+            Ok(()) => {}
+            Err(WilySearchError::FileNotFound) => return Err(anyhow!("`{}` not found", args.url)),
+            Err(WilySearchError::NotADirectory) => {
+                return Err(anyhow!("`{}` is not a directory", args.url))
+            }
+            Err(WilySearchError::PermissionDenied) => {
+                return Err(anyhow!("permission denied for `{}`", args.url))
+            }
+            Err(WilySearchError::InvalidPattern(e)) => {
+                return Err(Error::msg(e).context(anyhow!("invalid pattern `{}`", args.pattern)))
+            }
+        }
+
+        let mut receiver = receiver.established().await?;
+
+        while let Some(found) = receiver.next_item().await? {
+            print_match(&found);
+        }
+
+        Ok(())
+    }
+    .await;
+
+    wily.client().shutdown();
+    join.await??;
+    res
+}
+
+fn print_match(found: &SearchMatch) {
+    match found.metadata.file_type {
+        FileType::File => println!("{}", found.path),
+        FileType::Directory => println!("{}/", found.path),
+        FileType::SymLink => println!("{}@", found.path),
+    }
+}
+
+fn unix_ms_ago(ago: Duration) -> Result<i64> {
+    let ago = i64::try_from(ago.as_millis())
+        .with_context(|| anyhow!("duration is too far in the past"))?;
+
+    Ok(Utc::now().timestamp_millis().saturating_sub(ago))
+}
+
+#[derive(Debug, Copy, Clone, ValueEnum)]
+enum Type {
+    /// Regular files.
+    File,
+
+    /// Directories.
+    Directory,
+
+    /// Symbolic links.
+    Symlink,
+}
+
+impl From<Type> for FileType {
+    fn from(file_type: Type) -> Self {
+        match file_type {
+            Type::File => Self::File,
+            Type::Directory => Self::Directory,
+            Type::Symlink => Self::SymLink,
+        }
+    }
+}
@@ -0,0 +1,726 @@
+use crate::schemas::{
+    features, ArchiveChunk, Compression, DeltaOp, FileVersion, HashAlgorithm, ServerInfo,
+    WilyArchiveArgs, WilyArchiveError, WilyDeltaArgs, WilyDeltaError, WilyHashArgs, WilyHashError,
+    WilyHashOk, WilyProxy, WilyReadArgs, WilyReadError, WilyReadOk,
+};
+use crate::{delta, utils};
+use aldrin::core::Bytes;
+use aldrin::{PendingReceiver, Receiver};
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use flate2::read::GzDecoder;
+use std::io::{self, Read, SeekFrom};
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+use url::Url;
+
+const CHANNEL_CAPACITY: u32 = 16;
+const PART_SUFFIX: &str = ".wily-part";
+const INFO_SUFFIX: &str = ".wily-part.info";
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// URL of a shared file, or of a directory if `--archive` is used.
+    url: Url,
+
+    /// Local destination path.
+    ///
+    /// If this is not specified, then the file will be saved in the current directory under its
+    /// remote name. If this refers to a directory, then the file will be saved inside it.
+    ///
+    /// Interrupted downloads are resumed automatically, unless the remote file has been modified in
+    /// the meantime. If the destination exists already, then only the differences are transferred.
+    /// Finished downloads are verified against a hash of the remote file.
+    ///
+    /// With `--archive`, the archive is saved as `<name>.tar` (plus a suffix for the compression)
+    /// by default. With `--extract`, this is the directory into which the archive is unpacked.
+    dest: Option<PathBuf>,
+
+    /// Download a whole directory as a tar archive.
+    #[clap(short, long)]
+    archive: bool,
+
+    /// Unpack the archive instead of saving it.
+    #[clap(short = 'x', long, requires = "archive")]
+    extract: bool,
+
+    /// Compress the archive during the transfer.
+    #[clap(short, long, value_enum, requires = "archive")]
+    compress: Option<ArchiveCompression>,
+}
+
+pub async fn run(args: Args) -> Result<()> {
+    // Plain downloads use hashes and delta transfers only if the daemon supports them.
+    let required: &[&str] = if args.archive {
+        &[features::ARCHIVE]
+    } else {
+        &[]
+    };
+
+    let (wily, server, join) = utils::connect_wily_with_info(&args.url, required).await?;
+
+    let res = if args.archive {
+        let compression = args.compress.map(Into::into).unwrap_or_default();
+
+        download_archive(
+            &wily,
+            &args.url,
+            args.dest.as_deref(),
+            compression,
+            args.extract,
+        )
+        .await
+    } else {
+        match destination(&args.url, args.dest.as_deref()).await {
+            Ok(dest) => download(&wily, &server, &args.url, &dest).await,
+            Err(e) => Err(e),
+        }
+    };
+
+    wily.client().shutdown();
+    join.await??;
+    res
+}
+
+pub async fn download(wily: &WilyProxy, server: &ServerInfo, url: &Url, dest: &Path) -> Result<()> {
+    let path = utils::url_path(url)?;
+    let part = with_suffix(dest, PART_SUFFIX);
+    let info = with_suffix(dest, INFO_SUFFIX);
+
+    if let Some((offset, version)) = partial_download(&part, &info).await {
+        match start_read(wily, url, &path, offset, Some(version)).await? {
+            Some((read, receiver)) => {
+                println!("Resuming download of `{url}` at byte {offset}.");
+                receive(url, &part, offset, read.size, receiver).await?;
+                verify(wily, server, url, &part, &info, &version_of(&read)).await?;
+                return finish(&part, &info, dest).await;
+            }
+
+            None => println!("`{url}` was modified since the download started. Starting over."),
+        }
+    }
+
+    // An existing local copy only needs to be updated.
+    let is_nonempty_file = fs::symlink_metadata(dest)
+        .await
+        .is_ok_and(|metadata| metadata.is_file() && (metadata.len() > 0));
+
+    if is_nonempty_file && server.supports(features::DELTA) {
+        if let Some(version) = update(wily, url, &path, dest, &part, &info).await? {
+            verify(wily, server, url, &part, &info, &version).await?;
+            return finish(&part, &info, dest).await;
+        }
+    }
+
+    let (read, receiver) = start_read(wily, url, &path, 0, None)
+        .await?
+        .ok_or_else(|| anyhow!("`{url}` was modified"))?;
+
+    let version = version_of(&read);
+    write_info(&info, &version).await?;
+    receive(url, &part, 0, read.size, receiver).await?;
+    verify(wily, server, url, &part, &info, &version).await?;
+    finish(&part, &info, dest).await
+}
+
+/// Updates an existing local copy of a file with a delta transfer.
+///
+/// The new version is written to `part` and described by `info`, like a regular download, so that
+/// an interrupted update can be resumed. `Ok(None)` is returned if the daemon rejects the delta
+/// transfer, in which case the file must be downloaded in full.
+async fn update(
+    wily: &WilyProxy,
+    url: &Url,
+    path: &str,
+    dest: &Path,
+    part: &Path,
+    info: &Path,
+) -> Result<Option<FileVersion>> {
+    let local_size = fs::metadata(dest)
+        .await
+        .with_context(|| anyhow!("failed to query `{}`", dest.display()))?
+        .len();
+
+    let block_size = delta::block_size(local_size);
+    let owned = dest.to_owned();
+
+    let signatures = tokio::task::spawn_blocking(move || delta::signatures(&owned, block_size))
+        .await?
+        .with_context(|| anyhow!("failed to read `{}`", dest.display()))?;
+
+    let (sender, receiver) = wily
+        .client()
+        .create_channel_with_claimed_receiver(CHANNEL_CAPACITY)
+        .await?;
+
+    let res = wily
+        .delta(&WilyDeltaArgs {
+            path: path.to_owned(),
+            block_size,
+            signatures,
+            sender: sender.unbind(),
+        })
+        .await?;
+
+    let delta = match res {
+        Ok(delta) => delta,
+        Err(WilyDeltaError::FileNotFound) => return Err(anyhow!("`{url}` not found")),
+        Err(WilyDeltaError::NotAFile) => return Err(anyhow!("`{url}` is not a file")),
+        Err(WilyDeltaError::PermissionDenied) => {
+            return Err(anyhow!("permission denied for `{url}`"))
+        }
+        Err(WilyDeltaError::InvalidSignatures) => return Ok(None),
+    };
+
+    let version = FileVersion {
+        size: delta.size,
+        modified_unix_ms: delta.modified_unix_ms,
+    };
+
+    // A leftover info file from an earlier download would otherwise describe the wrong version.
+    write_info(info, &version).await?;
+
+    println!("Updating existing copy of `{url}`.");
+    let (size, literal) = apply_delta(dest, part, block_size, receiver).await?;
+
+    if size != delta.size {
+        return Err(anyhow!(
+            "update of `{url}` is incomplete ({size} of {} bytes)",
+            delta.size
+        ));
+    }
+
+    println!("Transferred {literal} of {size} bytes.");
+    Ok(Some(version))
+}
+
+/// Reconstructs a file from `base` and a stream of delta ops.
+///
+/// Returns the total size of the file and the number of literal bytes that were received.
+async fn apply_delta(
+    base: &Path,
+    part: &Path,
+    block_size: u32,
+    receiver: PendingReceiver<DeltaOp>,
+) -> Result<(u64, u64)> {
+    let mut receiver = receiver.established().await?;
+
+    let mut base_file = fs::File::open(base)
+        .await
+        .with_context(|| anyhow!("failed to open `{}`", base.display()))?;
+
+    let mut file = fs::File::create(part)
+        .await
+        .with_context(|| anyhow!("failed to create `{}`", part.display()))?;
+
+    let mut block = vec![0; block_size as usize];
+    let mut size = 0;
+    let mut literal = 0;
+
+    while let Some(op) = receiver.next_item().await? {
+        match op {
+            DeltaOp::Copy(copy) => {
+                base_file
+                    .seek(SeekFrom::Start(copy.block * block_size as u64))
+                    .await
+                    .with_context(|| anyhow!("failed to seek in `{}`", base.display()))?;
+
+                for _ in 0..copy.count {
+                    base_file
+                        .read_exact(&mut block)
+                        .await
+                        .with_context(|| anyhow!("failed to read `{}`", base.display()))?;
+
+                    file.write_all(&block)
+                        .await
+                        .with_context(|| anyhow!("failed to write to `{}`", part.display()))?;
+                }
+
+                size += copy.count as u64 * block_size as u64;
+            }
+
+            DeltaOp::Literal(data) => {
+                file.write_all(&data)
+                    .await
+                    .with_context(|| anyhow!("failed to write to `{}`", part.display()))?;
+
+                size += data.len() as u64;
+                literal += data.len() as u64;
+            }
+        }
+    }
+
+    file.sync_all()
+        .await
+        .with_context(|| anyhow!("failed to write to `{}`", part.display()))?;
+
+    Ok((size, literal))
+}
+
+fn version_of(read: &WilyReadOk) -> FileVersion {
+    FileVersion {
+        size: read.size,
+        modified_unix_ms: read.modified_unix_ms,
+    }
+}
+
+/// Verifies a finished download in `part` against the hash of the remote file.
+///
+/// This happens before `part` replaces the destination, so that a mismatch never affects an
+/// existing local copy. Verification is skipped if the daemon doesn't support hashes or if the
+/// remote file was modified after the download.
+async fn verify(
+    wily: &WilyProxy,
+    server: &ServerInfo,
+    url: &Url,
+    part: &Path,
+    info: &Path,
+    version: &FileVersion,
+) -> Result<()> {
+    if !server.supports(features::HASH) {
+        println!("The daemon doesn't support hashes. Skipping verification of `{url}`.");
+        return Ok(());
+    }
+
+    let remote = remote_hash(wily, url).await?;
+
+    if (remote.size != version.size) || (remote.modified_unix_ms != version.modified_unix_ms) {
+        println!("`{url}` was modified after the download. Skipping verification.");
+        return Ok(());
+    }
+
+    let local = local_hash(part, remote.algorithm).await?;
+    if local[..] == remote.hash[..] {
+        return Ok(());
+    }
+
+    // A corrupt download must not be resumed either.
+    let _ = fs::remove_file(part).await;
+    let _ = fs::remove_file(info).await;
+
+    Err(anyhow!(
+        "hash mismatch for `{url}` (expected {}, got {}); the download has been discarded",
+        utils::format_hash(&remote.hash),
+        utils::format_hash(&local)
+    ))
+}
+
+pub async fn remote_hash(wily: &WilyProxy, url: &Url) -> Result<WilyHashOk> {
+    let res = wily
+        .hash(&WilyHashArgs {
+            path: utils::url_path(url)?,
+            algorithm: Some(HashAlgorithm::Blake3),
+        })
+        .await?;
+
+    match res {
+        Ok(hash) => Ok(hash),
+        Err(WilyHashError::FileNotFound) => Err(anyhow!("`{url}` not found")),
+        Err(WilyHashError::NotAFile) => Err(anyhow!("`{url}` is not a file")),
+        Err(WilyHashError::PermissionDenied) => Err(anyhow!("permission denied for `{url}`")),
+        Err(WilyHashError::IoError) => Err(anyhow!("failed to hash `{url}`")),
+    }
+}
+
+pub async fn local_hash(path: &Path, algorithm: HashAlgorithm) -> Result<Vec<u8>> {
+    let owned = path.to_owned();
+
+    tokio::task::spawn_blocking(move || utils::hash_file(&owned, algorithm))
+        .await?
+        .with_context(|| anyhow!("failed to hash `{}`", path.display()))
+}
+
+pub async fn download_archive(
+    wily: &WilyProxy,
+    url: &Url,
+    dest: Option<&Path>,
+    compression: Compression,
+    extract: bool,
+) -> Result<()> {
+    let path = utils::url_path(url)?;
+
+    let (sender, receiver) = wily
+        .client()
+        .create_channel_with_claimed_receiver(CHANNEL_CAPACITY)
+        .await?;
+
+    let res = wily
+        .archive(&WilyArchiveArgs {
+            path,
+            sender: sender.unbind(),
+            compression: Some(compression),
+        })
+        .await?;
+
+    match res {
+        Ok(()) => {}
+        Err(WilyArchiveError::FileNotFound) => return Err(anyhow!("`{url}` not found")),
+        Err(WilyArchiveError::NotADirectory) => return Err(anyhow!("`{url}` is not a directory")),
+        Err(WilyArchiveError::PermissionDenied) => {
+            return Err(anyhow!("permission denied for `{url}`"))
+        }
+    }
+
+    if extract {
+        let dest = dest.unwrap_or(Path::new("."));
+        extract_archive(url, receiver, dest, compression).await
+    } else {
+        let dest = archive_destination(url, dest, compression).await?;
+        save_archive(url, receiver, &dest).await
+    }
+}
+
+async fn save_archive(
+    url: &Url,
+    receiver: PendingReceiver<ArchiveChunk>,
+    dest: &Path,
+) -> Result<()> {
+    let mut receiver = receiver.established().await?;
+    let part = with_suffix(dest, PART_SUFFIX);
+
+    let mut file = fs::File::create(&part)
+        .await
+        .with_context(|| anyhow!("failed to create `{}`", part.display()))?;
+
+    while let Some(chunk) = next_archive_chunk(url, &mut receiver).await? {
+        file.write_all(&chunk)
+            .await
+            .with_context(|| anyhow!("failed to write to `{}`", part.display()))?;
+    }
+
+    file.sync_all()
+        .await
+        .with_context(|| anyhow!("failed to write to `{}`", part.display()))?;
+
+    fs::rename(&part, dest).await.with_context(|| {
+        anyhow!(
+            "failed to rename `{}` to `{}`",
+            part.display(),
+            dest.display()
+        )
+    })
+}
+
+async fn extract_archive(
+    url: &Url,
+    receiver: PendingReceiver<ArchiveChunk>,
+    dest: &Path,
+    compression: Compression,
+) -> Result<()> {
+    let mut receiver = receiver.established().await?;
+
+    fs::create_dir_all(dest)
+        .await
+        .with_context(|| anyhow!("failed to create `{}`", dest.display()))?;
+
+    // Unpacking is done by a blocking task, which is fed through an mpsc channel.
+    let (chunks, reader) = mpsc::channel(CHANNEL_CAPACITY as usize);
+    let dest_owned = dest.to_owned();
+    let unpack = tokio::task::spawn_blocking(move || {
+        unpack_archive(ChannelReader::new(reader), &dest_owned, compression)
+    });
+
+    let received = async {
+        while let Some(chunk) = next_archive_chunk(url, &mut receiver).await? {
+            if chunks.send(chunk.to_vec()).await.is_err() {
+                // The unpacking task failed and will report the error.
+                break;
+            }
+        }
+
+        Ok::<_, anyhow::Error>(())
+    }
+    .await;
+
+    drop(chunks);
+    let unpacked = unpack.await?;
+
+    received?;
+    unpacked.with_context(|| anyhow!("failed to unpack `{url}` into `{}`", dest.display()))
+}
+
+/// Returns the next chunk of an archive, or `None` once the daemon has finished it.
+///
+/// Streams that end before the daemon has finished the archive are rejected as truncated.
+async fn next_archive_chunk(
+    url: &Url,
+    receiver: &mut Receiver<ArchiveChunk>,
+) -> Result<Option<Bytes>> {
+    let chunk = receiver
+        .next_item()
+        .await
+        .with_context(|| anyhow!("download of `{url}` failed"))?;
+
+    match chunk {
+        Some(ArchiveChunk::Data(data)) => Ok(Some(data)),
+        Some(ArchiveChunk::Done) => Ok(None),
+        Some(ArchiveChunk::Failed) => Err(anyhow!("daemon failed to archive `{url}`")),
+        None => Err(anyhow!("archive of `{url}` is truncated")),
+    }
+}
+
+fn unpack_archive(reader: ChannelReader, dest: &Path, compression: Compression) -> io::Result<()> {
+    match compression {
+        Compression::None => tar::Archive::new(reader).unpack(dest),
+        Compression::Gzip => tar::Archive::new(GzDecoder::new(reader)).unpack(dest),
+        Compression::Zstd => tar::Archive::new(zstd::Decoder::new(reader)?).unpack(dest),
+    }
+}
+
+async fn archive_destination(
+    url: &Url,
+    dest: Option<&Path>,
+    compression: Compression,
+) -> Result<PathBuf> {
+    let path = utils::url_path(url)?;
+
+    let name = Path::new(&path)
+        .file_name()
+        .ok_or_else(|| anyhow!("URL `{url}` doesn't refer to a directory"))?;
+
+    let mut name = name.to_owned();
+    name.push(match compression {
+        Compression::None => ".tar",
+        Compression::Gzip => ".tar.gz",
+        Compression::Zstd => ".tar.zst",
+    });
+
+    match dest {
+        Some(dest) => {
+            let is_dir = fs::metadata(dest)
+                .await
+                .map(|metadata| metadata.is_dir())
+                .unwrap_or(false);
+
+            if is_dir {
+                Ok(dest.join(name))
+            } else {
+                Ok(dest.to_owned())
+            }
+        }
+
+        None => Ok(PathBuf::from(name)),
+    }
+}
+
+/// Starts reading a remote file at `offset`.
+///
+/// `Ok(None)` is returned if `if_unchanged` was specified and the file has been modified.
+async fn start_read(
+    wily: &WilyProxy,
+    url: &Url,
+    path: &str,
+    offset: u64,
+    if_unchanged: Option<FileVersion>,
+) -> Result<Option<(WilyReadOk, PendingReceiver<Bytes>)>> {
+    let (sender, receiver) = wily
+        .client()
+        .create_channel_with_claimed_receiver(CHANNEL_CAPACITY)
+        .await?;
+
+    let res = wily
+        .read(&WilyReadArgs {
+            path: path.to_owned(),
+            sender: sender.unbind(),
+            offset: Some(offset),
+            length: None,
+            if_unchanged,
+            follow: None,
+        })
+        .await?;
+
+    match res {
+        Ok(read) => Ok(Some((read, receiver))),
+        Err(WilyReadError::FileNotFound) => Err(anyhow!("`{url}` not found")),
+        Err(WilyReadError::NotAFile) => Err(anyhow!("`{url}` is not a file")),
+        Err(WilyReadError::PermissionDenied) => Err(anyhow!("permission denied for `{url}`")),
+        Err(WilyReadError::Modified) | Err(WilyReadError::InvalidRange) => Ok(None),
+    }
+}
+
+async fn receive(
+    url: &Url,
+    part: &Path,
+    offset: u64,
+    size: u64,
+    receiver: PendingReceiver<Bytes>,
+) -> Result<()> {
+    let mut receiver = receiver.established().await?;
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(offset == 0)
+        .open(part)
+        .await
+        .with_context(|| anyhow!("failed to open `{}`", part.display()))?;
+
+    file.seek(SeekFrom::Start(offset))
+        .await
+        .with_context(|| anyhow!("failed to seek in `{}`", part.display()))?;
+
+    let mut received = offset;
+
+    while let Some(chunk) = receiver.next_item().await? {
+        file.write_all(&chunk)
+            .await
+            .with_context(|| anyhow!("failed to write to `{}`", part.display()))?;
+
+        received += chunk.len() as u64;
+    }
+
+    file.sync_all()
+        .await
+        .with_context(|| anyhow!("failed to write to `{}`", part.display()))?;
+
+    if received != size {
+        return Err(anyhow!(
+            "download of `{url}` is incomplete ({received} of {size} bytes)"
+        ));
+    }
+
+    Ok(())
+}
+
+async fn finish(part: &Path, info: &Path, dest: &Path) -> Result<()> {
+    fs::rename(part, dest).await.with_context(|| {
+        anyhow!(
+            "failed to rename `{}` to `{}`",
+            part.display(),
+            dest.display()
+        )
+    })?;
+
+    let _ = fs::remove_file(info).await;
+    Ok(())
+}
+
+/// Returns the length and remote version of a previously interrupted download.
+async fn partial_download(part: &Path, info: &Path) -> Option<(u64, FileVersion)> {
+    let len = fs::metadata(part).await.ok()?.len();
+    let info = fs::read_to_string(info).await.ok()?;
+
+    let mut info = info.split_whitespace();
+    let size = info.next()?.parse().ok()?;
+    let modified_unix_ms = match info.next()? {
+        "-" => None,
+        modified_unix_ms => Some(modified_unix_ms.parse().ok()?),
+    };
+
+    if len > size {
+        return None;
+    }
+
+    Some((
+        len,
+        FileVersion {
+            size,
+            modified_unix_ms,
+        },
+    ))
+}
+
+async fn write_info(info: &Path, version: &FileVersion) -> Result<()> {
+    let contents = match version.modified_unix_ms {
+        Some(modified_unix_ms) => format!("{} {}\n", version.size, modified_unix_ms),
+        None => format!("{} -\n", version.size),
+    };
+
+    fs::write(info, contents)
+        .await
+        .with_context(|| anyhow!("failed to write `{}`", info.display()))
+}
+
+/// Checks whether a file name belongs to an interrupted download.
+pub fn is_partial_download(name: &str) -> bool {
+    name.ends_with(PART_SUFFIX) || name.ends_with(INFO_SUFFIX)
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+async fn destination(url: &Url, dest: Option<&Path>) -> Result<PathBuf> {
+    let path = utils::url_path(url)?;
+
+    let name = Path::new(&path)
+        .file_name()
+        .ok_or_else(|| anyhow!("URL `{url}` doesn't refer to a file"))?;
+
+    match dest {
+        Some(dest) => {
+            let is_dir = fs::metadata(dest)
+                .await
+                .map(|metadata| metadata.is_dir())
+                .unwrap_or(false);
+
+            if is_dir {
+                Ok(dest.join(name))
+            } else {
+                Ok(dest.to_owned())
+            }
+        }
+
+        None => Ok(PathBuf::from(name)),
+    }
+}
+
+/// Adapts an mpsc channel to `Read`.
+struct ChannelReader {
+    receiver: mpsc::Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl ChannelReader {
+    fn new(receiver: mpsc::Receiver<Vec<u8>>) -> Self {
+        Self {
+            receiver,
+            chunk: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            match self.receiver.blocking_recv() {
+                Some(chunk) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+
+                None => return Ok(0),
+            }
+        }
+
+        let len = buf.len().min(self.chunk.len() - self.pos);
+        buf[..len].copy_from_slice(&self.chunk[self.pos..self.pos + len]);
+        self.pos += len;
+
+        Ok(len)
+    }
+}
+
+#[derive(Debug, Copy, Clone, ValueEnum)]
+enum ArchiveCompression {
+    /// Compress with gzip.
+    Gzip,
+
+    /// Compress with zstd.
+    Zstd,
+}
+
+impl From<ArchiveCompression> for Compression {
+    fn from(compression: ArchiveCompression) -> Self {
+        match compression {
+            ArchiveCompression::Gzip => Self::Gzip,
+            ArchiveCompression::Zstd => Self::Zstd,
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use crate::schemas::{features, Identity, ServerInfo};
+use crate::utils;
+use anyhow::Result;
+use chrono::{Local, TimeZone, Utc};
+use url::Url;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// URL of a daemon.
+    url: Url,
+}
+
+pub async fn run(args: Args) -> Result<()> {
+    let (wily, join) = utils::connect_wily(&args.url, &[features::IDENTITY]).await?;
+
+    let res = async {
+        let info = wily.server_info().await??;
+        let identity = wily.identity().await??;
+
+        print_identity(&identity);
+        print_server_info(&info);
+
+        Ok(())
+    }
+    .await;
+
+    wily.client().shutdown();
+    join.await??;
+    res
+}
+
+pub fn print_identity(identity: &Identity) {
+    println!("Name:     {}", identity.name);
+    println!("Instance: {}", identity.instance_id);
+    println!(
+        "Host:     {} ({}, {})",
+        identity.hostname, identity.os, identity.arch
+    );
+
+    if let Some(started) = Local
+        .timestamp_millis_opt(identity.started_unix_ms)
+        .single()
+    {
+        println!("Started:  {}", started.naive_local());
+    }
+
+    let uptime_ms = Utc::now().timestamp_millis() - identity.started_unix_ms;
+    if uptime_ms >= 0 {
+        println!("Uptime:   {}", format_uptime(uptime_ms / 1000));
+    }
+}
+
+fn print_server_info(info: &ServerInfo) {
+    println!("Version:  {} (protocol {})", info.version, info.protocol);
+    println!("Features: {}", info.features.join(", "));
+    println!("Limits:");
+    println!("  Chunk size:     {} bytes", info.max_chunk_size);
+    println!("  List entries:   {}", info.max_list_limit);
+    println!("  Search results: {}", info.max_search_limit);
+    println!("  Block size:     {} bytes", info.max_block_size);
+    println!("  Signatures:     {}", info.max_signatures);
+}
+
+fn format_uptime(secs: i64) -> String {
+    let days = secs / 86400;
+    let hours = (secs % 86400) / 3600;
+    let minutes = (secs % 3600) / 60;
+
+    if days > 0 {
+        format!("{days}d {hours}h {minutes}m")
+    } else if hours > 0 {
+        format!("{hours}h {minutes}m")
+    } else if minutes > 0 {
+        format!("{minutes}m {}s", secs % 60)
+    } else {
+        format!("{secs}s")
+    }
+}
@@ -0,0 +1,66 @@
+use crate::schemas::{DirEntry, FileType, WilyListArgs, WilyListError, WilyProxy};
+use crate::utils;
+use anyhow::{anyhow, Result};
+use url::Url;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// URL of a shared directory.
+    url: Url,
+}
+
+pub async fn run(args: Args) -> Result<()> {
+    let (wily, join) = utils::connect_wily(&args.url, &[]).await?;
+
+    let res = list_directory(&wily, &args.url).await.map(|entries| {
+        for entry in entries {
+            print_entry(&entry);
+        }
+    });
+
+    wily.client().shutdown();
+    join.await??;
+    res
+}
+
+pub async fn list_directory(wily: &WilyProxy, url: &Url) -> Result<Vec<DirEntry>> {
+    let path = utils::url_path(url)?;
+    let mut entries = Vec::new();
+    let mut cursor = None;
+
+    loop {
+        let res = wily
+            .list(&WilyListArgs {
+                path: path.clone(),
+                cursor,
+                limit: None,
+            })
+            .await?;
+
+        let list = match res {
+            Ok(list) => list,
+            Err(WilyListError::FileNotFound) => return Err(anyhow!("`{url}` not found")),
+            Err(WilyListError::NotADirectory) => return Err(anyhow!("`{url}` is not a directory")),
+            Err(WilyListError::PermissionDenied) => {
+                return Err(anyhow!("permission denied for `{url}`"))
+            }
+        };
+
+        entries.extend(list.entries);
+
+        match list.cursor {
+            Some(next) => cursor = Some(next),
+            None => break,
+        }
+    }
+
+    Ok(entries)
+}
+
+fn print_entry(entry: &DirEntry) {
+    match entry.metadata.file_type {
+        FileType::File => println!("{}", entry.name),
+        FileType::Directory => println!("{}/", entry.name),
+        FileType::SymLink => println!("{}@", entry.name),
+    }
+}
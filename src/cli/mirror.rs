@@ -0,0 +1,278 @@
+use super::{get, ls};
+use crate::schemas::{features, DirEntry, FileType, ServerInfo, WilyProxy};
+use crate::utils;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use filetime::FileTime;
+use std::collections::HashSet;
+use std::fs::Metadata;
+use std::path::{Component, Path, PathBuf};
+use tokio::fs;
+use url::Url;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// URL of a shared directory.
+    url: Url,
+
+    /// Local directory to mirror into.
+    ///
+    /// The directory is created if it doesn't exist yet.
+    dir: PathBuf,
+
+    /// Delete local files and directories which don't exist remotely.
+    #[clap(short, long)]
+    delete: bool,
+
+    /// Compare files of equal size by their hash instead of their modification time.
+    #[clap(short, long)]
+    checksum: bool,
+}
+
+pub async fn run(args: Args) -> Result<()> {
+    let required: &[&str] = if args.checksum {
+        &[features::HASH]
+    } else {
+        &[]
+    };
+
+    let (wily, server, join) = utils::connect_wily_with_info(&args.url, required).await?;
+
+    let res = Mirror::new(&wily, &server, args.delete, args.checksum)
+        .run(&args.url, &args.dir)
+        .await;
+
+    wily.client().shutdown();
+    join.await??;
+    res
+}
+
+struct Mirror<'a> {
+    wily: &'a WilyProxy,
+    server: &'a ServerInfo,
+    delete: bool,
+    checksum: bool,
+    downloaded: usize,
+    unchanged: usize,
+    deleted: usize,
+}
+
+impl<'a> Mirror<'a> {
+    fn new(wily: &'a WilyProxy, server: &'a ServerInfo, delete: bool, checksum: bool) -> Self {
+        Self {
+            wily,
+            server,
+            delete,
+            checksum,
+            downloaded: 0,
+            unchanged: 0,
+            deleted: 0,
+        }
+    }
+
+    async fn run(mut self, url: &Url, dir: &Path) -> Result<()> {
+        let mut pending = vec![(url.clone(), dir.to_owned())];
+
+        while let Some((url, dir)) = pending.pop() {
+            fs::create_dir_all(&dir)
+                .await
+                .with_context(|| anyhow!("failed to create `{}`", dir.display()))?;
+
+            let entries = ls::list_directory(self.wily, &url).await?;
+
+            // Names come from the daemon and must never lead outside of `dir`.
+            for entry in &entries {
+                check_name(&entry.name).with_context(|| anyhow!("failed to mirror `{url}`"))?;
+            }
+
+            if self.delete {
+                self.delete_removed(&dir, &entries).await?;
+            }
+
+            for entry in entries {
+                let url = child_url(&url, &entry.name)?;
+                let path = dir.join(&entry.name);
+
+                match entry.metadata.file_type {
+                    FileType::Directory => {
+                        self.replace_non_directory(&path).await?;
+                        pending.push((url, path));
+                    }
+
+                    FileType::File => self.mirror_file(&url, &path, &entry).await?,
+                    FileType::SymLink => println!("Skipping symbolic link `{url}`."),
+                }
+            }
+        }
+
+        println!(
+            "Downloaded {} file(s), {} unchanged, {} deleted.",
+            self.downloaded, self.unchanged, self.deleted
+        );
+
+        Ok(())
+    }
+
+    async fn mirror_file(&mut self, url: &Url, path: &Path, entry: &DirEntry) -> Result<()> {
+        match fs::symlink_metadata(path).await {
+            Ok(local) if local.is_dir() => {
+                if !self.delete {
+                    println!(
+                        "Skipping `{url}`, because `{}` is a directory.",
+                        path.display()
+                    );
+                    return Ok(());
+                }
+
+                remove(path, &local).await?;
+                self.deleted += 1;
+            }
+
+            Ok(local) => {
+                if self.is_unchanged(url, path, &local, entry).await? {
+                    self.unchanged += 1;
+                    return Ok(());
+                }
+            }
+
+            Err(_) => {}
+        }
+
+        println!("Downloading `{url}`.");
+        get::download(self.wily, self.server, url, path).await?;
+        self.downloaded += 1;
+
+        // The modification time is what tells unchanged files apart the next time.
+        set_modified(path, entry)
+    }
+
+    async fn is_unchanged(
+        &self,
+        url: &Url,
+        path: &Path,
+        local: &Metadata,
+        entry: &DirEntry,
+    ) -> Result<bool> {
+        if !local.is_file() || (entry.metadata.size != Some(local.len())) {
+            return Ok(false);
+        }
+
+        if !self.checksum {
+            return Ok(same_modified(local, entry));
+        }
+
+        let remote = get::remote_hash(self.wily, url).await?;
+        let hash = get::local_hash(path, remote.algorithm).await?;
+
+        if hash[..] != remote.hash[..] {
+            return Ok(false);
+        }
+
+        // Adopt the remote modification time, so that mirroring without `--checksum` agrees.
+        set_modified(path, entry)?;
+        Ok(true)
+    }
+
+    /// Removes a local file, that is a directory remotely, if deleting is enabled.
+    async fn replace_non_directory(&mut self, path: &Path) -> Result<()> {
+        if !self.delete {
+            return Ok(());
+        }
+
+        match fs::symlink_metadata(path).await {
+            Ok(local) if !local.is_dir() => {
+                remove(path, &local).await?;
+                self.deleted += 1;
+                Ok(())
+            }
+
+            _ => Ok(()),
+        }
+    }
+
+    /// Deletes everything inside `dir`, that isn't among `entries`.
+    async fn delete_removed(&mut self, dir: &Path, entries: &[DirEntry]) -> Result<()> {
+        let remote: HashSet<_> = entries.iter().map(|entry| entry.name.as_str()).collect();
+
+        let mut local = fs::read_dir(dir)
+            .await
+            .with_context(|| anyhow!("failed to read `{}`", dir.display()))?;
+
+        while let Some(entry) = local.next_entry().await? {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+
+            // Keep partial downloads, so that they can be resumed.
+            if remote.contains(&*name) || get::is_partial_download(&name) {
+                continue;
+            }
+
+            let path = entry.path();
+            println!("Deleting `{}`.", path.display());
+
+            let metadata = entry.metadata().await?;
+            remove(&path, &metadata).await?;
+            self.deleted += 1;
+        }
+
+        Ok(())
+    }
+}
+
+fn same_modified(local: &Metadata, entry: &DirEntry) -> bool {
+    let Some(remote_modified) = entry.metadata.modified_unix_ms else {
+        // Without a remote modification time, the size is all there is to compare.
+        return true;
+    };
+
+    local
+        .modified()
+        .map(|modified| DateTime::<Utc>::from(modified).timestamp_millis() == remote_modified)
+        .unwrap_or(false)
+}
+
+fn set_modified(path: &Path, entry: &DirEntry) -> Result<()> {
+    let Some(modified_unix_ms) = entry.metadata.modified_unix_ms else {
+        return Ok(());
+    };
+
+    let modified = FileTime::from_unix_time(
+        modified_unix_ms.div_euclid(1000),
+        (modified_unix_ms.rem_euclid(1000) * 1_000_000) as u32,
+    );
+
+    filetime::set_file_mtime(path, modified)
+        .with_context(|| anyhow!("failed to set mtime of `{}`", path.display()))
+}
+
+async fn remove(path: &Path, metadata: &Metadata) -> Result<()> {
+    let res = if metadata.is_dir() {
+        fs::remove_dir_all(path).await
+    } else {
+        fs::remove_file(path).await
+    };
+
+    res.with_context(|| anyhow!("failed to remove `{}`", path.display()))
+}
+
+/// Checks that `name` is a single, normal path component.
+fn check_name(name: &str) -> Result<()> {
+    let mut components = Path::new(name).components();
+
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) if !name.contains('/') => Ok(()),
+        _ => Err(anyhow!("invalid entry name `{name}`")),
+    }
+}
+
+fn child_url(url: &Url, name: &str) -> Result<Url> {
+    let mut child = url.clone();
+
+    child
+        .path_segments_mut()
+        .map_err(|()| anyhow!("invalid URL `{url}`"))?
+        .pop_if_empty()
+        .push(name);
+
+    Ok(child)
+}
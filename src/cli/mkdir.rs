@@ -0,0 +1,34 @@
+use crate::schemas::{WilyMkdirArgs, WilyMkdirError};
+use crate::utils;
+use anyhow::{anyhow, Result};
+use url::Url;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// URL of the directory to create inside a writable share.
+    url: Url,
+}
+
+pub async fn run(args: Args) -> Result<()> {
+    let (wily, join) = utils::connect_wily(&args.url, &[]).await?;
+
+    let res = wily
+        .mkdir(&WilyMkdirArgs {
+            path: utils::url_path(&args.url)?,
+        })
+        .await?;
+
+    let url = &args.url;
+    let res = match res {
+        Ok(()) => Ok(()),
+        Err(WilyMkdirError::NotFound) => Err(anyhow!("parent of `{url}` not found")),
+        Err(WilyMkdirError::AlreadyExists) => Err(anyhow!("`{url}` exists already")),
+        Err(WilyMkdirError::ReadOnlyShare) => Err(anyhow!("`{url}` is in a read-only share")),
+        Err(WilyMkdirError::PermissionDenied) => Err(anyhow!("permission denied for `{url}`")),
+        Err(WilyMkdirError::IoError) => Err(anyhow!("failed to create `{url}`")),
+    };
+
+    wily.client().shutdown();
+    join.await??;
+    res
+}
@@ -0,0 +1,53 @@
+use crate::schemas::{WilyRenameArgs, WilyRenameError};
+use crate::utils;
+use anyhow::{anyhow, Result};
+use url::Url;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// URL of the file or directory to rename.
+    from: Url,
+
+    /// New URL of the file or directory, which must be inside the same share.
+    to: Url,
+
+    /// Replace the destination, if it exists already.
+    #[clap(short, long)]
+    force: bool,
+}
+
+pub async fn run(args: Args) -> Result<()> {
+    // Both URLs must refer to the same daemon, including its default port and use of TLS.
+    if utils::verify_url(&args.from)? != utils::verify_url(&args.to)? {
+        return Err(anyhow!(
+            "`{}` and `{}` refer to different hosts",
+            args.from,
+            args.to
+        ));
+    }
+
+    let (wily, join) = utils::connect_wily(&args.from, &[]).await?;
+
+    let res = wily
+        .rename(&WilyRenameArgs {
+            from: utils::url_path(&args.from)?,
+            to: utils::url_path(&args.to)?,
+            overwrite: Some(args.force),
+        })
+        .await?;
+
+    let (from, to) = (&args.from, &args.to);
+    let res = match res {
+        Ok(()) => Ok(()),
+        Err(WilyRenameError::NotFound) => Err(anyhow!("`{from}` not found")),
+        Err(WilyRenameError::AlreadyExists) => Err(anyhow!("`{to}` exists already")),
+        Err(WilyRenameError::ReadOnlyShare) => Err(anyhow!("`{from}` is in a read-only share")),
+        Err(WilyRenameError::PermissionDenied) => Err(anyhow!("permission denied for `{from}`")),
+        Err(WilyRenameError::CrossShare) => Err(anyhow!("cannot move `{from}` to another share")),
+        Err(WilyRenameError::IoError) => Err(anyhow!("failed to rename `{from}` to `{to}`")),
+    };
+
+    wily.client().shutdown();
+    join.await??;
+    res
+}
@@ -0,0 +1,131 @@
+use crate::schemas::{
+    FileType, WilyProxy, WilyQueryArgs, WilyQueryOk, WilyWriteArgs, WilyWriteError,
+};
+use crate::utils;
+use aldrin::core::Bytes;
+use aldrin::PendingSender;
+use anyhow::{anyhow, Context, Result};
+use std::path::{Path, PathBuf};
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+use url::Url;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// Path to a local file to upload.
+    file: PathBuf,
+
+    /// Destination URL inside a writable share.
+    ///
+    /// If this refers to a directory, then the file will be uploaded into it under its local name.
+    url: Url,
+
+    /// Overwrite the destination, if it exists already.
+    #[clap(short, long)]
+    force: bool,
+}
+
+pub async fn run(args: Args) -> Result<()> {
+    let (wily, join) = utils::connect_wily(&args.url, &[]).await?;
+
+    let res = upload(&wily, &args.file, &args.url, args.force).await;
+
+    wily.client().shutdown();
+    join.await??;
+    res
+}
+
+pub async fn upload(wily: &WilyProxy, file: &Path, url: &Url, overwrite: bool) -> Result<()> {
+    let mut path = utils::url_path(url)?;
+
+    if is_directory(wily, &path).await? {
+        let name = file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| anyhow!("invalid file name `{}`", file.display()))?;
+
+        if !path.ends_with('/') {
+            path.push('/');
+        }
+
+        path.push_str(name);
+    }
+
+    let local = File::open(file)
+        .await
+        .with_context(|| anyhow!("failed to open `{}`", file.display()))?;
+
+    let size = local
+        .metadata()
+        .await
+        .with_context(|| anyhow!("failed to query `{}`", file.display()))?
+        .len();
+
+    let (sender, receiver) = wily.client().create_channel_with_claimed_sender().await?;
+
+    let reply = wily.write(&WilyWriteArgs {
+        path: path.clone(),
+        receiver: receiver.unbind(),
+        size: Some(size),
+        overwrite: Some(overwrite),
+    });
+
+    // If sending fails, then the reply will usually carry a more precise error.
+    let sent = send_file(local, sender).await;
+
+    match reply.await? {
+        Ok(write) => {
+            sent.with_context(|| anyhow!("failed to upload `{}`", file.display()))?;
+
+            // Drop boxes pick a different name if the requested one is already taken.
+            if let Some(name) = write.name {
+                if !path.ends_with(&format!("/{name}")) {
+                    println!("`{}` was saved as `{name}`.", file.display());
+                }
+            }
+
+            Ok(())
+        }
+
+        Err(WilyWriteError::FileNotFound) => Err(anyhow!("parent of `{url}` not found")),
+        Err(WilyWriteError::NotAFile) => Err(anyhow!("`{url}` is not a file")),
+        Err(WilyWriteError::AlreadyExists) => Err(anyhow!("`{url}` exists already")),
+        Err(WilyWriteError::ReadOnlyShare) => Err(anyhow!("`{url}` is in a read-only share")),
+        Err(WilyWriteError::PermissionDenied) => Err(anyhow!("permission denied for `{url}`")),
+        Err(WilyWriteError::Incomplete) => Err(anyhow!("upload to `{url}` is incomplete")),
+        Err(WilyWriteError::IoError) => Err(anyhow!("failed to write `{url}`")),
+    }
+}
+
+async fn send_file(mut file: File, sender: PendingSender<Bytes>) -> Result<()> {
+    let mut sender = sender.established().await?;
+    let mut buf = vec![0; CHUNK_SIZE];
+
+    loop {
+        let len = file.read(&mut buf).await?;
+        if len == 0 {
+            break;
+        }
+
+        sender.send_item(&Bytes::new(&buf[..len])).await?;
+    }
+
+    sender.close().await?;
+    Ok(())
+}
+
+async fn is_directory(wily: &WilyProxy, path: &str) -> Result<bool> {
+    let res = wily
+        .query(&WilyQueryArgs {
+            path: path.to_owned(),
+        })
+        .await?;
+
+    match res {
+        Ok(WilyQueryOk::Root) => Ok(true),
+        Ok(WilyQueryOk::Metadata(metadata)) => Ok(metadata.file_type == FileType::Directory),
+        Err(_) => Ok(false),
+    }
+}
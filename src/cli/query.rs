@@ -1,6 +1,8 @@
-use crate::schemas::WilyQueryArgs;
+use super::info;
+use crate::schemas::{FileType, Metadata, WilyQueryArgs, WilyQueryError, WilyQueryOk};
 use crate::utils;
-use anyhow::{anyhow, Context, Error, Result};
+use anyhow::{anyhow, Result};
+use chrono::{Local, TimeZone};
 use url::Url;
 
 #[derive(Debug, clap::Args)]
@@ -10,19 +12,106 @@ pub struct Args {
 }
 
 pub async fn run(args: Args) -> Result<()> {
-    let (wily, join) = utils::connect_wily(&args.url).await?;
-
-    dbg!(&args.url);
+    let (wily, join) = utils::connect_wily(&args.url, &[]).await?;
 
     let res = wily
         .query(&WilyQueryArgs {
-            path: args.url.path().to_owned(),
+            path: utils::url_path(&args.url)?,
         })
         .await?;
 
-    dbg!(res);
+    let res = match res {
+        Ok(WilyQueryOk::Root) => {
+            println!("`{}` is the root of the daemon.", args.url);
+
+            // Older daemons don't know their identity.
+            if let Ok(Ok(identity)) = wily.identity().await {
+                println!();
+                info::print_identity(&identity);
+            }
+
+            Ok(())
+        }
+
+        Ok(WilyQueryOk::Metadata(metadata)) => {
+            print_metadata(&metadata);
+            Ok(())
+        }
+
+        Err(WilyQueryError::FileNotFound) => Err(anyhow!("`{}` not found", args.url)),
+        Err(WilyQueryError::PermissionDenied) => {
+            Err(anyhow!("permission denied for `{}`", args.url))
+        }
+    };
 
     wily.client().shutdown();
     join.await??;
-    Ok(())
+    res
+}
+
+fn print_metadata(metadata: &Metadata) {
+    print!("Type:     ");
+    match metadata.file_type {
+        FileType::File => println!("file"),
+        FileType::Directory => println!("directory"),
+        FileType::SymLink => println!("symbolic link"),
+    }
+
+    if let Some(ref target) = metadata.symlink_target {
+        println!("Target:   {target}");
+    }
+
+    if let Some(size) = metadata.size {
+        println!("Size:     {}", format_size(size));
+    }
+
+    if let Some(modified_unix_ms) = metadata.modified_unix_ms {
+        if let Some(modified) = Local.timestamp_millis_opt(modified_unix_ms).single() {
+            println!("Modified: {}", modified.naive_local());
+        }
+    }
+
+    if let Some(mode) = metadata.mode {
+        println!("Mode:     {} ({mode:04o})", format_mode(mode));
+    }
+
+    if let Some(readable) = metadata.readable {
+        println!("Readable: {}", if readable { "yes" } else { "no" });
+    }
+}
+
+fn format_size(size: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+
+    if size < 1024 {
+        return format!("{size} bytes");
+    }
+
+    let mut scaled = size as f64 / 1024.0;
+    let mut unit = 0;
+
+    while (scaled >= 1024.0) && (unit + 1 < UNITS.len()) {
+        scaled /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{scaled:.1} {} ({size} bytes)", UNITS[unit])
+}
+
+fn format_mode(mode: u32) -> String {
+    const BITS: [(u32, char); 9] = [
+        (0o400, 'r'),
+        (0o200, 'w'),
+        (0o100, 'x'),
+        (0o040, 'r'),
+        (0o020, 'w'),
+        (0o010, 'x'),
+        (0o004, 'r'),
+        (0o002, 'w'),
+        (0o001, 'x'),
+    ];
+
+    BITS.iter()
+        .map(|&(bit, c)| if mode & bit != 0 { c } else { '-' })
+        .collect()
 }
@@ -0,0 +1,39 @@
+use crate::schemas::{WilyRemoveArgs, WilyRemoveError};
+use crate::utils;
+use anyhow::{anyhow, Result};
+use url::Url;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// URL of the file or directory to remove.
+    url: Url,
+
+    /// Remove directories and their contents recursively.
+    #[clap(short, long)]
+    recursive: bool,
+}
+
+pub async fn run(args: Args) -> Result<()> {
+    let (wily, join) = utils::connect_wily(&args.url, &[]).await?;
+
+    let res = wily
+        .remove(&WilyRemoveArgs {
+            path: utils::url_path(&args.url)?,
+            recursive: Some(args.recursive),
+        })
+        .await?;
+
+    let url = &args.url;
+    let res = match res {
+        Ok(()) => Ok(()),
+        Err(WilyRemoveError::NotFound) => Err(anyhow!("`{url}` not found")),
+        Err(WilyRemoveError::NotEmpty) => Err(anyhow!("directory `{url}` is not empty")),
+        Err(WilyRemoveError::ReadOnlyShare) => Err(anyhow!("`{url}` is in a read-only share")),
+        Err(WilyRemoveError::PermissionDenied) => Err(anyhow!("permission denied for `{url}`")),
+        Err(WilyRemoveError::IoError) => Err(anyhow!("failed to remove `{url}`")),
+    };
+
+    wily.client().shutdown();
+    join.await??;
+    res
+}
@@ -1,7 +1,10 @@
-use crate::schemas::{DaemonShareArgs, DaemonShareError};
+use crate::schemas::{DaemonShareArgs, DaemonShareError, ShareAccess, SymlinkPolicy};
 use crate::utils;
-use anyhow::{anyhow, Context, Result, Error};
+use anyhow::{anyhow, Context, Error, Result};
+use chrono::{DateTime, Local, Utc};
+use clap::ValueEnum;
 use std::path::Path;
+use std::time::Duration;
 
 #[derive(Debug, clap::Args)]
 pub struct Args {
@@ -21,6 +24,22 @@ pub struct Args {
     /// Don't automatically enable the share.
     #[clap(short, long)]
     disabled: bool,
+
+    /// Whether remote peers may modify the share.
+    #[clap(short, long, value_enum, default_value_t = Access::ReadOnly)]
+    access: Access,
+
+    /// How to treat symbolic links inside the share.
+    #[clap(short, long, value_enum, default_value_t = Symlinks::FollowInside)]
+    symlinks: Symlinks,
+
+    /// Remove the share automatically after some time, e.g. `30m`, `2h` or `1d12h`.
+    #[clap(long, value_parser = utils::parse_duration, conflicts_with = "expires_at")]
+    expires_in: Option<Duration>,
+
+    /// Remove the share automatically at a specific time, e.g. `2024-03-21 18:00`.
+    #[clap(long, value_parser = utils::parse_datetime)]
+    expires_at: Option<DateTime<Local>>,
 }
 
 pub async fn run(args: Args) -> Result<()> {
@@ -31,13 +50,30 @@ pub async fn run(args: Args) -> Result<()> {
         .to_str()
         .with_context(|| anyhow!("non UTF-8 path `{}`", path.display()))?;
 
+    let expires_unix_ms = if let Some(expires_in) = args.expires_in {
+        let expires_in = i64::try_from(expires_in.as_millis())
+            .with_context(|| anyhow!("expiration time is too far in the future"))?;
+
+        Some(Utc::now().timestamp_millis().saturating_add(expires_in))
+    } else if let Some(expires_at) = args.expires_at {
+        if expires_at <= Local::now() {
+            return Err(anyhow!("expiration time `{expires_at}` is in the past"));
+        }
+
+        Some(expires_at.timestamp_millis())
+    } else {
+        None
+    };
+
     let res = daemon
         .share(&DaemonShareArgs {
             path: path.to_owned(),
             name: args.name,
             persist: Some(args.persist),
-            expires_unix_ms: None,
+            expires_unix_ms,
             disabled: Some(args.disabled),
+            symlinks: Some(args.symlinks.into()),
+            access: Some(args.access.into()),
         })
         .await?;
 
@@ -53,9 +89,55 @@ pub async fn run(args: Args) -> Result<()> {
         Err(DaemonShareError::InvalidName(e)) => Err(Error::msg(e)),
         Err(DaemonShareError::DuplicateName(name)) => Err(anyhow!("duplicate share name `{name}`")),
         Err(DaemonShareError::RelativePath) => unreachable!(),
+        Err(DaemonShareError::PersistFailed) => Err(anyhow!("failed to save persisted shares")),
     };
 
     daemon.client().shutdown();
     join.await??;
     res
 }
+
+#[derive(Debug, Copy, Clone, ValueEnum)]
+enum Symlinks {
+    /// Follow symbolic links only if their target is inside the share.
+    FollowInside,
+
+    /// Refuse to access symbolic links.
+    Refuse,
+
+    /// Show symbolic links as such, but never follow them.
+    Expose,
+}
+
+impl From<Symlinks> for SymlinkPolicy {
+    fn from(symlinks: Symlinks) -> Self {
+        match symlinks {
+            Symlinks::FollowInside => Self::FollowInside,
+            Symlinks::Refuse => Self::Refuse,
+            Symlinks::Expose => Self::Expose,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, ValueEnum)]
+enum Access {
+    /// Remote peers can only read files.
+    ReadOnly,
+
+    /// Remote peers can read and write files.
+    ReadWrite,
+
+    /// Remote peers can only upload new files into the root directory, but neither list nor
+    /// download anything.
+    DropBox,
+}
+
+impl From<Access> for ShareAccess {
+    fn from(access: Access) -> Self {
+        match access {
+            Access::ReadOnly => Self::ReadOnly,
+            Access::ReadWrite => Self::ReadWrite,
+            Access::DropBox => Self::DropBox,
+        }
+    }
+}
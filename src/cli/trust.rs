@@ -0,0 +1,44 @@
+use crate::known_hosts::KnownHosts;
+use crate::{tls, utils};
+use anyhow::{anyhow, Result};
+use url::Url;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// `wilys://` URL of a daemon.
+    url: Url,
+
+    /// Certificate fingerprint to trust, as logged by the daemon.
+    ///
+    /// If this is not specified, then the daemon is contacted and its current certificate is
+    /// trusted.
+    #[clap(short, long)]
+    fingerprint: Option<String>,
+}
+
+pub async fn run(args: Args) -> Result<()> {
+    let known_host = utils::known_host(&args.url)?;
+
+    let fingerprint = match args.fingerprint {
+        Some(fingerprint) => tls::parse_fingerprint(&fingerprint)
+            .ok_or_else(|| anyhow!("invalid fingerprint `{fingerprint}`"))?,
+
+        None => utils::peer_fingerprint(&args.url).await?,
+    };
+
+    let mut known_hosts = KnownHosts::lock()?;
+
+    match known_hosts.insert(known_host.clone(), fingerprint.clone()) {
+        Some(old) if old == fingerprint => {
+            println!("`{known_host}` is already trusted with fingerprint {fingerprint}.");
+            return Ok(());
+        }
+
+        Some(old) => {
+            println!("Trusting `{known_host}` with fingerprint {fingerprint} instead of {old}.")
+        }
+        None => println!("Trusting `{known_host}` with fingerprint {fingerprint}."),
+    }
+
+    known_hosts.save()
+}
@@ -31,6 +31,8 @@ pub async fn run(args: Args) -> Result<()> {
         Err(DaemonUnshareError::StaticShare) => {
             Err(anyhow!("cannot remove static share `{}`", args.name))
         }
+
+        Err(DaemonUnshareError::PersistFailed) => Err(anyhow!("failed to save persisted shares")),
     };
 
     daemon.client().shutdown();
@@ -0,0 +1,24 @@
+use crate::known_hosts::KnownHosts;
+use crate::utils;
+use anyhow::{anyhow, Result};
+use url::Url;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// `wilys://` URL of a daemon.
+    url: Url,
+}
+
+pub async fn run(args: Args) -> Result<()> {
+    let known_host = utils::known_host(&args.url)?;
+    let mut known_hosts = KnownHosts::lock()?;
+
+    let fingerprint = known_hosts
+        .remove(&known_host)
+        .ok_or_else(|| anyhow!("`{known_host}` is not a known host"))?;
+
+    known_hosts.save()?;
+
+    println!("No longer trusting `{known_host}` with fingerprint {fingerprint}.");
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use crate::schemas::{features, WatchEvent, WilyWatchArgs, WilyWatchError};
+use crate::utils;
+use anyhow::{anyhow, Result};
+use url::Url;
+
+const CHANNEL_CAPACITY: u32 = 16;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// URL of a shared file or directory.
+    url: Url,
+
+    /// Also watch all subdirectories.
+    #[clap(short, long)]
+    recursive: bool,
+}
+
+pub async fn run(args: Args) -> Result<()> {
+    let (wily, join) = utils::connect_wily(&args.url, &[features::WATCH]).await?;
+
+    let res = async {
+        let path = utils::url_path(&args.url)?;
+        let base = path.trim_end_matches('/').to_owned();
+
+        let (sender, receiver) = wily
+            .client()
+            .create_channel_with_claimed_receiver(CHANNEL_CAPACITY)
+            .await?;
+
+        let res = wily
+            .watch(&WilyWatchArgs {
+                path,
+                sender: sender.unbind(),
+                recursive: Some(args.recursive),
+            })
+            .await?;
+
+        match res {
+            Ok(()) => {}
+            Err(WilyWatchError::FileNotFound) => return Err(anyhow!("`{}` not found", args.url)),
+            Err(WilyWatchError::PermissionDenied) => {
+                return Err(anyhow!("permission denied for `{}`", args.url))
+            }
+            Err(WilyWatchError::IoError) => return Err(anyhow!("failed to watch `{}`", args.url)),
+        }
+
+        let mut receiver = receiver.established().await?;
+
+        let mut removed = false;
+        let mut unavailable = false;
+
+        while let Some(event) = receiver.next_item().await? {
+            match event {
+                WatchEvent::Deleted(ref path) if *path == base => removed = true,
+                WatchEvent::Unavailable => unavailable = true,
+                _ => {}
+            }
+
+            print_event(&event);
+        }
+
+        if removed {
+            println!("`{}` was removed.", args.url);
+        } else if unavailable {
+            println!("The share of `{}` is no longer available.", args.url);
+        } else {
+            println!("Watching `{}` ended.", args.url);
+        }
+
+        Ok(())
+    }
+    .await;
+
+    wily.client().shutdown();
+    join.await??;
+    res
+}
+
+fn print_event(event: &WatchEvent) {
+    match event {
+        WatchEvent::Created(path) => println!("created  {path}"),
+        WatchEvent::Modified(path) => println!("modified {path}"),
+        WatchEvent::Deleted(path) => println!("deleted  {path}"),
+        WatchEvent::Renamed(rename) => println!("renamed  {} -> {}", rename.from, rename.to),
+        WatchEvent::Overflow => println!("overflow (some events were lost)"),
+        WatchEvent::KeepAlive | WatchEvent::Unavailable => {}
+    }
+}
@@ -1,23 +1,64 @@
+mod archive;
+mod config;
 mod daemon_calls;
+mod hash_cache;
+mod identity;
 mod private_bus;
 mod public_bus;
+mod resolve;
+mod search;
+mod state;
+mod tls;
+mod watch;
 mod wily_calls;
 
 use crate::logging::Logging;
-use crate::schemas::{Daemon, Share, Wily, DAEMON_OBJECT_UUID, WILY_OBJECT_UUID};
+use crate::schemas::{
+    Daemon, DaemonUnsharedEvent, Identity, Share, ShareType, UnshareReason, Wily,
+    DAEMON_OBJECT_UUID, WILY_OBJECT_UUID,
+};
+use crate::utils;
 use aldrin::Object;
-use anyhow::Result;
-use parking_lot::RwLock;
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use config::Config;
+use hash_cache::HashCache;
+use parking_lot::{Mutex, RwLock};
 use private_bus::PrivateBus;
 use public_bus::PublicBus;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::signal::unix::{signal, Signal, SignalKind};
 
 #[derive(Debug, clap::Args)]
 pub struct Args {
     #[clap(flatten)]
     logging: Logging,
+
+    /// Path to the daemon's configuration file.
+    ///
+    /// If this is not specified, then `wily/daemon.toml` inside the user's configuration directory
+    /// is used, if it exists.
+    #[clap(short, long)]
+    config: Option<PathBuf>,
+
+    /// Address on which to accept public connections.
+    ///
+    /// This can be given multiple times and overrides the addresses from the configuration file.
+    /// Addresses are of the form `ip`, `ip:port` or `[ipv6]:port`. The port defaults to 9999 and
+    /// port 0 picks an ephemeral port. `[::]` accepts both IPv4 and IPv6 connections.
+    #[clap(short, long, value_parser = utils::parse_listen_addr)]
+    listen: Vec<SocketAddr>,
+
+    /// Address on which to accept public connections with TLS.
+    ///
+    /// This works like `--listen`, except that the port defaults to 9998. A self-signed
+    /// certificate is generated on first use, unless one is set in the configuration file.
+    #[clap(long, value_parser = utils::parse_tls_listen_addr)]
+    tls_listen: Vec<SocketAddr>,
 }
 
 pub async fn run(args: Args) -> Result<()> {
@@ -36,13 +77,34 @@ struct Mainloop {
     _daemon_obj: Object,
     daemon: Daemon,
     shares: Arc<RwLock<HashMap<String, Share>>>,
+    state_path: PathBuf,
+    shadowed: Vec<Share>,
+    hash_cache: Arc<Mutex<HashCache>>,
+    identity: Identity,
 }
 
 impl Mainloop {
-    async fn new(_args: Args) -> Result<Self> {
+    async fn new(args: Args) -> Result<Self> {
         log::info!("Starting daemon.");
 
-        let public_bus = PublicBus::new().await?;
+        let config = Config::load(args.config.as_deref())?;
+        let identity = identity::new(config.name)?;
+        let listen = listen_addrs(args.listen, config.listen)?;
+        let tls_listen = tls_listen_addrs(args.tls_listen, config.tls_listen)?;
+
+        let tls = if tls_listen.is_empty() {
+            None
+        } else {
+            let tls = tls::acceptor(
+                config.tls_cert.as_deref(),
+                config.tls_key.as_deref(),
+                &identity.hostname,
+            )?;
+
+            Some(tls)
+        };
+
+        let public_bus = PublicBus::new(&listen, &tls_listen, tls).await?;
         let private_bus = PrivateBus::new().await?;
 
         let sigint = signal(SignalKind::interrupt())?;
@@ -54,6 +116,53 @@ impl Mainloop {
         let daemon_obj = private_bus.create_object(DAEMON_OBJECT_UUID).await?;
         let daemon = Daemon::new(&daemon_obj).await?;
 
+        let mut shares = HashMap::new();
+
+        for share in config.shares {
+            let share = share.into_share()?;
+
+            if shares.contains_key(&share.name) {
+                return Err(anyhow!("duplicate static share `{}`", share.name));
+            }
+
+            log::info!("Adding static share `{}` (`{}`).", share.name, share.path);
+            shares.insert(share.name.clone(), share);
+        }
+
+        let state_path = state::default_path()?;
+        let mut persisted = HashSet::new();
+        let mut shadowed = Vec::new();
+
+        for share in state::load(&state_path)? {
+            // Skipping a duplicate would silently drop it from the state file on the next save.
+            if !persisted.insert(share.name.clone()) {
+                return Err(anyhow!(
+                    "duplicate persisted share `{}` in `{}`",
+                    share.name,
+                    state_path.display()
+                ));
+            }
+
+            if shares.contains_key(&share.name) {
+                // Keep it around, so that it is written back to the state file and becomes active
+                // again once the static share is removed.
+                log::warn!(
+                    "Persisted share `{}` is shadowed by a static share of the same name.",
+                    share.name
+                );
+                shadowed.push(share);
+                continue;
+            }
+
+            log::info!(
+                "Restoring persisted share `{}` (`{}`).",
+                share.name,
+                share.path
+            );
+
+            shares.insert(share.name.clone(), share);
+        }
+
         Ok(Self {
             shutdown: false,
             public_bus,
@@ -64,7 +173,11 @@ impl Mainloop {
             wily,
             _daemon_obj: daemon_obj,
             daemon,
-            shares: Arc::new(RwLock::new(HashMap::new())),
+            shares: Arc::new(RwLock::new(shares)),
+            state_path,
+            shadowed,
+            hash_cache: Arc::new(Mutex::new(HashCache::new())),
+            identity,
         })
     }
 
@@ -72,6 +185,8 @@ impl Mainloop {
         log::info!("Entering mainloop.");
 
         while !self.shutdown {
+            let next_expiry = self.next_expiry();
+
             tokio::select! {
                 Some(call) = self.wily.next_call() => {
                     match call {
@@ -87,6 +202,10 @@ impl Mainloop {
                     }
                 }
 
+                () = sleep_until_unix_ms(next_expiry.unwrap_or(0)), if next_expiry.is_some() => {
+                    self.expire_shares()?;
+                }
+
                 () = self.public_bus.wait() => {
                     log::error!("Public bus shut down unexpectedly.");
                     break;
@@ -117,4 +236,109 @@ impl Mainloop {
 
         Ok(())
     }
+
+    fn next_expiry(&self) -> Option<i64> {
+        self.shares
+            .read()
+            .values()
+            .filter_map(Share::expires_unix_ms)
+            .min()
+    }
+
+    fn expire_shares(&self) -> Result<()> {
+        let now = Utc::now().timestamp_millis();
+        let mut shares = self.shares.write();
+
+        let expired: Vec<_> = shares
+            .values()
+            .filter(|share| {
+                share
+                    .expires_unix_ms()
+                    .is_some_and(|expires| expires <= now)
+            })
+            .map(|share| share.name.clone())
+            .collect();
+
+        let mut save = false;
+
+        for name in expired {
+            let share = shares.remove(&name).unwrap();
+            log::info!("Share `{}` (`{}`) expired.", share.name, share.path);
+
+            if let ShareType::Persisted(_) = share.share_type {
+                save = true;
+            }
+
+            self.daemon.unshared(&DaemonUnsharedEvent {
+                share,
+                reason: UnshareReason::Expired,
+            })?;
+        }
+
+        drop(shares);
+
+        // Shares that failed to be removed from the state expire again right after a restart.
+        if save {
+            if let Err(e) = self.save_state(&self.persisted_shares()) {
+                log::error!("Failed to save persisted shares: {e:#}.");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns all active persisted shares.
+    fn persisted_shares(&self) -> Vec<Share> {
+        self.shares
+            .read()
+            .values()
+            .filter(|share| matches!(share.share_type, ShareType::Persisted(_)))
+            .cloned()
+            .collect()
+    }
+
+    /// Saves `shares`, which must contain all active persisted shares, and the shadowed ones.
+    ///
+    /// This writes and syncs a file and must not be called while `shares` is locked.
+    fn save_state(&self, shares: &[Share]) -> Result<()> {
+        state::save(&self.state_path, shares, &self.shadowed)
+    }
+}
+
+fn listen_addrs(args: Vec<SocketAddr>, config: Option<Vec<String>>) -> Result<Vec<SocketAddr>> {
+    if !args.is_empty() {
+        return Ok(args);
+    }
+
+    match config {
+        Some(addrs) => addrs
+            .iter()
+            .map(|addr| utils::parse_listen_addr(addr))
+            .collect::<Result<_>>()
+            .with_context(|| anyhow!("invalid listen address in configuration")),
+
+        None => Ok(vec![SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            utils::WILY_PORT,
+        )]),
+    }
+}
+
+fn tls_listen_addrs(args: Vec<SocketAddr>, config: Option<Vec<String>>) -> Result<Vec<SocketAddr>> {
+    if !args.is_empty() {
+        return Ok(args);
+    }
+
+    config
+        .unwrap_or_default()
+        .iter()
+        .map(|addr| utils::parse_tls_listen_addr(addr))
+        .collect::<Result<_>>()
+        .with_context(|| anyhow!("invalid TLS listen address in configuration"))
+}
+
+async fn sleep_until_unix_ms(ts_unix_ms: i64) {
+    let now = Utc::now().timestamp_millis();
+    let delay = ts_unix_ms.saturating_sub(now).max(0) as u64;
+    tokio::time::sleep(Duration::from_millis(delay)).await;
 }
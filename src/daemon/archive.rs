@@ -0,0 +1,260 @@
+use super::resolve::ResolvedPath;
+use crate::schemas::{Compression, SymlinkPolicy};
+use flate2::write::GzEncoder;
+use std::ffi::OsString;
+use std::fs::{self, File, Metadata};
+use std::io::{self, ErrorKind, Read, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use tar::{Builder, EntryType, Header, HeaderMode};
+use tokio::sync::mpsc;
+
+const ZSTD_LEVEL: i32 = 3;
+
+/// Writes a tar archive of a directory into `sender`, in chunks of about `chunk_size` bytes.
+///
+/// The archive is generated on the fly and contains the directory itself as its only top-level
+/// entry, named `name`. Entries that cannot be read are skipped with a warning, so that a single
+/// unreadable file doesn't abort the whole archive. Any error after an entry's header has been
+/// written is returned, because the archive is broken at that point.
+///
+/// This function blocks and must be called via `tokio::task::spawn_blocking`.
+pub fn write_archive(
+    dir: &ResolvedPath,
+    name: &str,
+    compression: Compression,
+    sender: mpsc::Sender<Vec<u8>>,
+    chunk_size: usize,
+) -> io::Result<()> {
+    let writer = ChannelWriter::new(sender, chunk_size);
+
+    let mut writer = match compression {
+        Compression::None => build(dir, name, writer)?,
+
+        Compression::Gzip => {
+            let encoder = GzEncoder::new(writer, flate2::Compression::default());
+            build(dir, name, encoder)?.finish()?
+        }
+
+        Compression::Zstd => {
+            let encoder = zstd::Encoder::new(writer, ZSTD_LEVEL)?;
+            build(dir, name, encoder)?.finish()?
+        }
+    };
+
+    writer.flush()
+}
+
+fn build<W: Write>(dir: &ResolvedPath, name: &str, writer: W) -> io::Result<W> {
+    let mut builder = Builder::new(writer);
+    builder.mode(HeaderMode::Complete);
+    builder.follow_symlinks(false);
+
+    let mut ancestors = Vec::new();
+    let Some(entry) = Entry::open(dir, dir.path.clone(), &ancestors)? else {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "not a directory"));
+    };
+
+    append_entry(&mut builder, dir, entry, Path::new(name), &mut ancestors)?;
+    builder.into_inner()
+}
+
+/// An entry of the archive, which has been checked to be readable.
+enum Entry {
+    Directory {
+        path: PathBuf,
+        metadata: Metadata,
+        names: Vec<OsString>,
+    },
+
+    File {
+        file: File,
+        metadata: Metadata,
+    },
+
+    Symlink {
+        metadata: Metadata,
+        target: PathBuf,
+    },
+}
+
+impl Entry {
+    /// Opens an entry, subject to the share's symlink policy.
+    ///
+    /// `Ok(None)` is returned for entries that are never archived.
+    fn open(
+        dir: &ResolvedPath,
+        mut path: PathBuf,
+        ancestors: &[PathBuf],
+    ) -> io::Result<Option<Self>> {
+        let mut metadata = fs::symlink_metadata(&path)?;
+
+        if metadata.is_symlink() {
+            match dir.symlinks {
+                SymlinkPolicy::FollowInside => {
+                    let target = fs::canonicalize(&path)?;
+                    if !target.starts_with(&dir.root) {
+                        return Ok(None);
+                    }
+
+                    metadata = fs::metadata(&target)?;
+                    path = target;
+                }
+
+                SymlinkPolicy::Refuse => return Ok(None),
+
+                SymlinkPolicy::Expose => {
+                    let target = fs::read_link(&path)?;
+                    return Ok(Some(Self::Symlink { metadata, target }));
+                }
+            }
+        }
+
+        if metadata.is_dir() {
+            // Followed symbolic links can point to a parent directory.
+            if ancestors.contains(&path) {
+                return Ok(None);
+            }
+
+            let mut names = fs::read_dir(&path)?
+                .map(|entry| entry.map(|entry| entry.file_name()))
+                .collect::<io::Result<Vec<_>>>()?;
+            names.sort();
+
+            Ok(Some(Self::Directory {
+                path,
+                metadata,
+                names,
+            }))
+        } else if metadata.is_file() {
+            let file = File::open(&path)?;
+            Ok(Some(Self::File { file, metadata }))
+        } else {
+            // Devices, sockets and FIFOs are never archived.
+            Ok(None)
+        }
+    }
+}
+
+fn append_entry<W: Write>(
+    builder: &mut Builder<W>,
+    dir: &ResolvedPath,
+    entry: Entry,
+    archive_path: &Path,
+    ancestors: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    match entry {
+        Entry::Directory {
+            path,
+            metadata,
+            names,
+        } => {
+            let mut header = header(&metadata, EntryType::Directory);
+            builder.append_data(&mut header, archive_path, io::empty())?;
+
+            ancestors.push(path.clone());
+            let res = append_children(builder, dir, &path, names, archive_path, ancestors);
+            ancestors.pop();
+            res
+        }
+
+        Entry::File { file, metadata } => {
+            let size = metadata.len();
+            let mut header = header(&metadata, EntryType::Regular);
+
+            // The size in the header is binding. Files that change while they are being archived
+            // are truncated or padded with zeros.
+            let data = file.take(size).chain(io::repeat(0)).take(size);
+            builder.append_data(&mut header, archive_path, data)
+        }
+
+        Entry::Symlink { metadata, target } => {
+            let mut header = header(&metadata, EntryType::Symlink);
+            builder.append_link(&mut header, archive_path, target)
+        }
+    }
+}
+
+fn append_children<W: Write>(
+    builder: &mut Builder<W>,
+    dir: &ResolvedPath,
+    path: &Path,
+    names: Vec<OsString>,
+    archive_path: &Path,
+    ancestors: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    // Every entry is opened before its header is written, so that unreadable entries can be
+    // skipped without breaking the archive.
+    for name in names {
+        let path = path.join(&name);
+
+        match Entry::open(dir, path.clone(), ancestors) {
+            Ok(Some(entry)) => {
+                append_entry(builder, dir, entry, &archive_path.join(&name), ancestors)?;
+            }
+
+            Ok(None) => {}
+            Err(e) => log::warn!("Skipping `{}`: {e}.", path.display()),
+        }
+    }
+
+    Ok(())
+}
+
+fn header(metadata: &Metadata, entry_type: EntryType) -> Header {
+    let mut header = Header::new_gnu();
+    header.set_metadata_in_mode(metadata, HeaderMode::Complete);
+    header.set_entry_type(entry_type);
+
+    if entry_type != EntryType::Regular {
+        header.set_size(0);
+    }
+
+    header
+}
+
+/// Adapts an mpsc channel to `Write`.
+struct ChannelWriter {
+    sender: mpsc::Sender<Vec<u8>>,
+    buf: Vec<u8>,
+    chunk_size: usize,
+}
+
+impl ChannelWriter {
+    fn new(sender: mpsc::Sender<Vec<u8>>, chunk_size: usize) -> Self {
+        Self {
+            sender,
+            buf: Vec::with_capacity(chunk_size),
+            chunk_size,
+        }
+    }
+
+    fn send(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+
+        let chunk = mem::replace(&mut self.buf, Vec::with_capacity(self.chunk_size));
+
+        self.sender
+            .blocking_send(chunk)
+            .map_err(|_| io::Error::new(ErrorKind::BrokenPipe, "channel closed"))
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.chunk_size - self.buf.len());
+        self.buf.extend_from_slice(&buf[..len]);
+
+        if self.buf.len() >= self.chunk_size {
+            self.send()?;
+        }
+
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send()
+    }
+}
@@ -0,0 +1,127 @@
+use super::daemon_calls;
+use crate::schemas::{Share, ShareAccess, ShareDisabled, ShareType, SymlinkPolicy};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const CONFIG_FILE: &str = "daemon.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Friendly name of the daemon, which defaults to `user@hostname`.
+    pub name: Option<String>,
+
+    /// Addresses on which the public bus accepts connections.
+    pub listen: Option<Vec<String>>,
+
+    /// Addresses on which the public bus accepts connections with TLS.
+    pub tls_listen: Option<Vec<String>>,
+
+    /// TLS certificate chain in PEM format. A self-signed certificate is used if this is not set.
+    pub tls_cert: Option<PathBuf>,
+
+    /// Private key of `tls_cert` in PEM format.
+    pub tls_key: Option<PathBuf>,
+
+    #[serde(default, rename = "share")]
+    pub shares: Vec<ShareConfig>,
+}
+
+impl Config {
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        match path {
+            Some(path) => Self::load_file(path),
+            None => match default_path() {
+                Some(path) if path.exists() => Self::load_file(&path),
+                _ => Ok(Self::default()),
+            },
+        }
+    }
+
+    fn load_file(path: &Path) -> Result<Self> {
+        log::info!("Loading configuration from `{}`.", path.display());
+
+        let contents = fs::read_to_string(path)
+            .with_context(|| anyhow!("failed to read `{}`", path.display()))?;
+
+        let config: Self = toml::from_str(&contents)
+            .with_context(|| anyhow!("failed to parse `{}`", path.display()))?;
+
+        if config
+            .name
+            .as_deref()
+            .is_some_and(|name| name.trim().is_empty())
+        {
+            return Err(anyhow!("empty daemon name in `{}`", path.display()));
+        }
+
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShareConfig {
+    name: Option<String>,
+    path: String,
+
+    #[serde(default)]
+    disabled: bool,
+
+    #[serde(default, with = "SymlinkPolicyDef")]
+    symlinks: SymlinkPolicy,
+
+    #[serde(default, with = "ShareAccessDef")]
+    access: ShareAccess,
+}
+
+impl ShareConfig {
+    pub fn into_share(self) -> Result<Share> {
+        let name = daemon_calls::share_name(self.name.as_deref(), &self.path)
+            .with_context(|| anyhow!("invalid static share `{}`", self.path))?
+            .to_owned();
+
+        if Path::new(&self.path).is_relative() {
+            return Err(anyhow!(
+                "static share `{name}` has a relative path `{}`",
+                self.path
+            ));
+        }
+
+        Ok(Share {
+            name,
+            path: self.path,
+            share_type: ShareType::Static,
+            disabled: ShareDisabled {
+                user: self.disabled,
+            },
+            symlinks: self.symlinks,
+            access: self.access,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "SymlinkPolicy", rename_all = "kebab-case")]
+pub(super) enum SymlinkPolicyDef {
+    FollowInside,
+    Refuse,
+    Expose,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "ShareAccess", rename_all = "kebab-case")]
+pub(super) enum ShareAccessDef {
+    ReadOnly,
+    ReadWrite,
+    DropBox,
+}
+
+fn default_path() -> Option<PathBuf> {
+    let mut path = dirs::config_dir()?;
+    path.push("wily");
+    path.push(CONFIG_FILE);
+    Some(path)
+}
@@ -2,11 +2,11 @@ use super::Mainloop;
 use crate::schemas::{
     DaemonDisableArgs, DaemonDisableError, DaemonEnableArgs, DaemonEnableError, DaemonFunction,
     DaemonShareArgs, DaemonShareError, DaemonUnshareArgs, DaemonUnshareError, DaemonUnsharedEvent,
-    Share, ShareDisabled, ShareType, TransientShare, UnshareReason,
+    PersistedShare, Share, ShareDisabled, ShareType, TransientShare, UnshareReason,
 };
 use aldrin::Promise;
 use anyhow::{anyhow, Result};
-use std::collections::hash_map::{Entry, HashMap};
+use std::collections::HashMap;
 use std::convert::Infallible;
 use std::path::Path;
 
@@ -19,6 +19,7 @@ impl Mainloop {
             DaemonFunction::List(promise) => self.daemon_list(promise),
             DaemonFunction::Enable(args, promise) => self.daemon_enable(args, promise),
             DaemonFunction::Disable(args, promise) => self.daemon_disable(args, promise),
+            DaemonFunction::ListenAddrs(promise) => self.daemon_listen_addrs(promise),
         }
     }
 
@@ -44,13 +45,11 @@ impl Mainloop {
             }
         };
 
-        let mut shares = self.shares.write();
-
-        let Entry::Vacant(entry) = shares.entry(name.to_owned()) else {
+        if self.shares.read().contains_key(name) {
             log::error!("Duplicate share name `{name}`.");
             promise.err(&DaemonShareError::DuplicateName(name.to_owned()))?;
             return Ok(());
-        };
+        }
 
         if Path::new(&args.path).is_relative() {
             log::error!("Cannot share relativ path `{}`.", args.path);
@@ -58,32 +57,53 @@ impl Mainloop {
             return Ok(());
         }
 
-        let share_type = if args.persist.unwrap_or(false) {
-            todo!()
+        let persist = args.persist.unwrap_or(false);
+
+        let share_type = if persist {
+            ShareType::Persisted(PersistedShare {
+                expires_unix_ms: args.expires_unix_ms,
+            })
         } else {
             ShareType::Transient(TransientShare {
                 expires_unix_ms: args.expires_unix_ms,
             })
         };
 
-        if args.disabled.unwrap_or(false) {
-            todo!()
-        }
+        let disabled = args.disabled.unwrap_or(false);
 
-        log::info!("Sharing `{}` as `{}`.", args.path, name);
+        if disabled {
+            log::info!("Sharing `{}` as `{}` (disabled).", args.path, name);
+        } else {
+            log::info!("Sharing `{}` as `{}`.", args.path, name);
+        }
 
         let share = Share {
             name: name.to_owned(),
             path: args.path,
             share_type,
-            disabled: ShareDisabled {
-                user: args.disabled.unwrap_or(false),
-            },
+            disabled: ShareDisabled { user: disabled },
+            symlinks: args.symlinks.unwrap_or_default(),
+            access: args.access.unwrap_or_default(),
         };
 
-        let share = entry.insert(share);
-        self.daemon.shared(share)?;
-        promise.ok(share)?;
+        // Save the state before adding the share, so that nothing changes if saving fails. Shares
+        // are only ever added or removed by the main loop, so the name is still available after.
+        if persist {
+            let mut persisted = self.persisted_shares();
+            persisted.push(share.clone());
+
+            if let Err(e) = self.save_state(&persisted) {
+                log::error!("Failed to persist share `{}`: {e:#}.", share.name);
+                promise.err(&DaemonShareError::PersistFailed)?;
+                return Ok(());
+            }
+        }
+
+        self.shares
+            .write()
+            .insert(share.name.clone(), share.clone());
+        self.daemon.shared(&share)?;
+        promise.ok(&share)?;
         Ok(())
     }
 
@@ -92,30 +112,40 @@ impl Mainloop {
         args: DaemonUnshareArgs,
         promise: Promise<Share, DaemonUnshareError>,
     ) -> Result<()> {
-        let mut shares = self.shares.write();
-
-        let entry = match shares.entry(args.name) {
-            Entry::Occupied(entry) => entry,
-
-            Entry::Vacant(entry) => {
-                log::error!("Cannot remove unknown share `{}`", entry.key());
-                promise.err(&DaemonUnshareError::UnknownShare)?;
-                return Ok(());
-            }
+        let Some(share_type) = self
+            .shares
+            .read()
+            .get(&args.name)
+            .map(|share| share.share_type.clone())
+        else {
+            log::error!("Cannot remove unknown share `{}`", args.name);
+            promise.err(&DaemonUnshareError::UnknownShare)?;
+            return Ok(());
         };
 
-        match entry.get().share_type {
+        match share_type {
             ShareType::Static => {
-                log::error!("Cannot remove static share `{}`", entry.key());
+                log::error!("Cannot remove static share `{}`", args.name);
                 promise.err(&DaemonUnshareError::StaticShare)?;
                 return Ok(());
             }
 
-            ShareType::Persisted(_) => todo!(),
+            ShareType::Persisted(_) => {
+                let mut persisted = self.persisted_shares();
+                persisted.retain(|share| share.name != args.name);
+
+                if let Err(e) = self.save_state(&persisted) {
+                    log::error!("Failed to remove persisted share `{}`: {e:#}.", args.name);
+                    promise.err(&DaemonUnshareError::PersistFailed)?;
+                    return Ok(());
+                }
+            }
+
             ShareType::Transient(_) => {}
         }
 
-        let share = entry.remove();
+        let share = self.shares.write().remove(&args.name).unwrap();
+
         log::info!("Removing share `{}` (`{}`).", share.name, share.path);
         promise.ok(&share)?;
 
@@ -133,24 +163,106 @@ impl Mainloop {
         Ok(())
     }
 
+    fn daemon_listen_addrs(&self, promise: Promise<Vec<String>, Infallible>) -> Result<()> {
+        log::info!("Listing listen addresses.");
+
+        promise.ok(&self.public_bus.urls().to_vec())?;
+        Ok(())
+    }
+
     fn daemon_enable(
         &self,
-        _args: DaemonEnableArgs,
-        _promise: Promise<Share, DaemonEnableError>,
+        args: DaemonEnableArgs,
+        promise: Promise<Share, DaemonEnableError>,
     ) -> Result<()> {
-        todo!()
+        match self.set_user_disabled(&args.name, false)? {
+            Ok(share) => promise.ok(&share)?,
+
+            Err(SetDisabledError::UnknownShare) => {
+                log::error!("Cannot enable unknown share `{}`.", args.name);
+                promise.err(&DaemonEnableError::UnknownShare)?;
+            }
+
+            Err(SetDisabledError::PersistFailed) => {
+                promise.err(&DaemonEnableError::PersistFailed)?;
+            }
+        }
+
+        Ok(())
     }
 
     fn daemon_disable(
         &self,
-        _args: DaemonDisableArgs,
-        _promise: Promise<Share, DaemonDisableError>,
+        args: DaemonDisableArgs,
+        promise: Promise<Share, DaemonDisableError>,
     ) -> Result<()> {
-        todo!()
+        match self.set_user_disabled(&args.name, true)? {
+            Ok(share) => promise.ok(&share)?,
+
+            Err(SetDisabledError::UnknownShare) => {
+                log::error!("Cannot disable unknown share `{}`.", args.name);
+                promise.err(&DaemonDisableError::UnknownShare)?;
+            }
+
+            Err(SetDisabledError::PersistFailed) => {
+                promise.err(&DaemonDisableError::PersistFailed)?;
+            }
+        }
+
+        Ok(())
     }
+
+    fn set_user_disabled(
+        &self,
+        name: &str,
+        disabled: bool,
+    ) -> Result<Result<Share, SetDisabledError>> {
+        let Some(mut share) = self.shares.read().get(name).cloned() else {
+            return Ok(Err(SetDisabledError::UnknownShare));
+        };
+
+        if share.disabled.user == disabled {
+            return Ok(Ok(share));
+        }
+
+        if disabled {
+            log::info!("Disabling share `{}` (`{}`).", share.name, share.path);
+        } else {
+            log::info!("Enabling share `{}` (`{}`).", share.name, share.path);
+        }
+
+        share.disabled.user = disabled;
+
+        if let ShareType::Persisted(_) = share.share_type {
+            let mut persisted = self.persisted_shares();
+
+            for other in &mut persisted {
+                if other.name == share.name {
+                    other.disabled.user = disabled;
+                }
+            }
+
+            if let Err(e) = self.save_state(&persisted) {
+                log::error!("Failed to persist share `{}`: {e:#}.", share.name);
+                return Ok(Err(SetDisabledError::PersistFailed));
+            }
+        }
+
+        if let Some(active) = self.shares.write().get_mut(name) {
+            active.disabled.user = disabled;
+        }
+
+        self.daemon.share_changed(&share)?;
+        Ok(Ok(share))
+    }
+}
+
+enum SetDisabledError {
+    UnknownShare,
+    PersistFailed,
 }
 
-fn share_name<'a>(name: Option<&'a str>, path: &'a str) -> Result<&'a str> {
+pub(super) fn share_name<'a>(name: Option<&'a str>, path: &'a str) -> Result<&'a str> {
     if let Some(name) = name {
         if name.is_empty() {
             Err(anyhow!("share name is empty"))
@@ -0,0 +1,74 @@
+use crate::schemas::HashAlgorithm;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+const MAX_ENTRIES: usize = 4096;
+
+/// Caches file hashes, keyed by path, size and modification time.
+#[derive(Debug, Default)]
+pub struct HashCache {
+    entries: HashMap<PathBuf, Vec<CachedHash>>,
+}
+
+#[derive(Debug)]
+struct CachedHash {
+    algorithm: HashAlgorithm,
+    size: u64,
+    modified_unix_ms: Option<i64>,
+    hash: Vec<u8>,
+}
+
+impl HashCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(
+        &self,
+        path: &Path,
+        algorithm: HashAlgorithm,
+        size: u64,
+        modified_unix_ms: Option<i64>,
+    ) -> Option<Vec<u8>> {
+        self.entries
+            .get(path)?
+            .iter()
+            .find(|cached| {
+                (cached.algorithm == algorithm)
+                    && (cached.size == size)
+                    && (cached.modified_unix_ms == modified_unix_ms)
+            })
+            .map(|cached| cached.hash.clone())
+    }
+
+    pub fn insert(
+        &mut self,
+        path: PathBuf,
+        algorithm: HashAlgorithm,
+        size: u64,
+        modified_unix_ms: Option<i64>,
+        hash: Vec<u8>,
+    ) {
+        if (self.entries.len() >= MAX_ENTRIES) && !self.entries.contains_key(&path) {
+            if let Some(evict) = self.entries.keys().next().cloned() {
+                self.entries.remove(&evict);
+            }
+        }
+
+        let cached = self.entries.entry(path).or_default();
+
+        // Hashes of older versions of the file are useless now.
+        cached.retain(|cached| {
+            (cached.algorithm != algorithm)
+                && (cached.size == size)
+                && (cached.modified_unix_ms == modified_unix_ms)
+        });
+
+        cached.push(CachedHash {
+            algorithm,
+            size,
+            modified_unix_ms,
+            hash,
+        });
+    }
+}
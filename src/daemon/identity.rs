@@ -0,0 +1,82 @@
+use crate::schemas::Identity;
+use crate::utils;
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use uuid::Uuid;
+
+const INSTANCE_ID_FILE: &str = "instance-id";
+const HOSTNAME_FILE: &str = "/proc/sys/kernel/hostname";
+
+/// Creates the daemon's identity.
+///
+/// The instance ID is generated once and then kept in the data directory, so that it survives
+/// restarts.
+pub fn new(name: Option<String>) -> Result<Identity> {
+    let mut path = utils::data_dir()?;
+    path.push(INSTANCE_ID_FILE);
+    let instance_id = instance_id(&path)?;
+
+    let hostname = hostname();
+    let name = name.unwrap_or_else(|| format!("{}@{hostname}", user()));
+
+    log::info!("Daemon `{name}` has instance ID {instance_id}.");
+
+    Ok(Identity {
+        name,
+        instance_id,
+        hostname,
+        os: env::consts::OS.to_owned(),
+        arch: env::consts::ARCH.to_owned(),
+        started_unix_ms: Utc::now().timestamp_millis(),
+    })
+}
+
+fn instance_id(path: &Path) -> Result<Uuid> {
+    match fs::read_to_string(path) {
+        Ok(contents) => {
+            return contents
+                .trim()
+                .parse()
+                .with_context(|| anyhow!("failed to parse `{}`", path.display()));
+        }
+
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+
+        Err(e) => {
+            return Err(e).with_context(|| anyhow!("failed to read `{}`", path.display()));
+        }
+    }
+
+    let instance_id = Uuid::new_v4();
+    log::info!("Generated new instance ID {instance_id}.");
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| anyhow!("failed to create directory `{}`", dir.display()))?;
+    }
+
+    fs::write(path, format!("{instance_id}\n"))
+        .with_context(|| anyhow!("failed to write `{}`", path.display()))?;
+
+    Ok(instance_id)
+}
+
+fn hostname() -> String {
+    fs::read_to_string(HOSTNAME_FILE)
+        .ok()
+        .map(|hostname| hostname.trim().to_owned())
+        .filter(|hostname| !hostname.is_empty())
+        .unwrap_or_else(|| "localhost".to_owned())
+}
+
+fn user() -> String {
+    env::var("USER")
+        .or_else(|_| env::var("LOGNAME"))
+        .ok()
+        .filter(|user| !user.is_empty())
+        .unwrap_or_else(|| "wily".to_owned())
+}
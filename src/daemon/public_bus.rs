@@ -4,35 +4,55 @@ use aldrin::core::tokio::TokioTransport;
 use aldrin::Handle as ClientHandle;
 use aldrin_broker::BrokerHandle;
 use anyhow::{anyhow, Context, Result};
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::future;
+use std::io;
+use std::net::SocketAddr;
 use std::ops::Deref;
+use std::task::Poll;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
 
-const BIND: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 9999);
+const BACKLOG: i32 = 128;
 
 pub struct PublicBus {
     client: ClientHandle,
     shutdown: ShutdownNotifier,
     join: JoinHandle<Result<()>>,
+    urls: Vec<String>,
 }
 
 impl PublicBus {
-    pub async fn new() -> Result<Self> {
-        log::info!("Listening on {BIND} for public connections.");
-
+    /// Starts listening on `addrs` and, using `tls`, on `tls_addrs`.
+    pub async fn new(
+        addrs: &[SocketAddr],
+        tls_addrs: &[SocketAddr],
+        tls: Option<TlsAcceptor>,
+    ) -> Result<Self> {
         let (shutdown, shutdown_mainloop) = ShutdownNotifier::new_pair();
-        let mainloop = Mainloop::new(shutdown_mainloop).await?;
+        let mainloop = Mainloop::new(addrs, tls_addrs, tls, shutdown_mainloop).await?;
         let client = mainloop.client().clone();
+        let urls = mainloop.urls()?;
         let join = tokio::spawn(mainloop.run());
 
+        for url in &urls {
+            log::info!("Listening on {url} for public connections.");
+        }
+
         Ok(Self {
             client,
             shutdown,
             join,
+            urls,
         })
     }
 
+    /// Returns the URLs of all listeners, including ephemeral ports.
+    pub fn urls(&self) -> &[String] {
+        &self.urls
+    }
+
     pub async fn wait(&mut self) {
         self.shutdown.wait().await
     }
@@ -57,21 +77,60 @@ impl Deref for PublicBus {
 struct Mainloop {
     shutdown: ShutdownNotifier,
     bus: Bus,
+    listeners: Vec<Listener>,
+    next_listener: usize,
+}
+
+struct Listener {
     listener: TcpListener,
+    tls: Option<TlsAcceptor>,
 }
 
 impl Mainloop {
-    async fn new(shutdown: ShutdownNotifier) -> Result<Self> {
-        let listener = TcpListener::bind(BIND)
-            .await
-            .with_context(|| anyhow!("failed to bind TCP listener to {BIND}"))?;
+    async fn new(
+        addrs: &[SocketAddr],
+        tls_addrs: &[SocketAddr],
+        tls: Option<TlsAcceptor>,
+        shutdown: ShutdownNotifier,
+    ) -> Result<Self> {
+        if addrs.is_empty() && tls_addrs.is_empty() {
+            return Err(anyhow!("no listen addresses for public connections"));
+        }
+
+        let all: Vec<_> = addrs.iter().chain(tls_addrs).collect();
+        let mut listeners = Vec::with_capacity(all.len());
+
+        for (i, &&addr) in all.iter().enumerate() {
+            // The IPv6 wildcard address also accepts IPv4 connections, unless any IPv4 address is
+            // bound separately on the same port, which would then conflict with it.
+            let only_v6 = !addr.ip().is_unspecified()
+                || all
+                    .iter()
+                    .any(|other| other.is_ipv4() && (other.port() == addr.port()));
+
+            let listener = bind(addr, only_v6)
+                .with_context(|| anyhow!("failed to bind TCP listener to {addr}"))?;
+
+            let tls = if i < addrs.len() {
+                None
+            } else {
+                let tls = tls
+                    .clone()
+                    .ok_or_else(|| anyhow!("no TLS configuration for {addr}"))?;
+
+                Some(tls)
+            };
+
+            listeners.push(Listener { listener, tls });
+        }
 
         let bus = Bus::new().await?;
 
         Ok(Self {
             shutdown,
             bus,
-            listener,
+            listeners,
+            next_listener: 0,
         })
     }
 
@@ -79,18 +138,38 @@ impl Mainloop {
         self.bus.client()
     }
 
+    fn urls(&self) -> Result<Vec<String>> {
+        self.listeners
+            .iter()
+            .map(|listener| {
+                let addr = listener
+                    .listener
+                    .local_addr()
+                    .with_context(|| anyhow!("failed to get local address of TCP listener"))?;
+
+                let scheme = if listener.tls.is_some() {
+                    "wilys"
+                } else {
+                    "wily"
+                };
+
+                Ok(format!("{scheme}://{addr}"))
+            })
+            .collect()
+    }
+
     async fn run(mut self) -> Result<()> {
         loop {
             tokio::select! {
                 () = self.shutdown.wait() => break,
                 () = self.bus.wait() => return Err(anyhow!("Public bus shut down unexpectedly")),
 
-                res = self.listener.accept() => {
-                    let (stream, addr) =
+                res = accept(&self.listeners, &mut self.next_listener) => {
+                    let (stream, addr, tls) =
                         res.with_context(|| anyhow!("failed to accept TCP connection"))?;
 
                     let broker = self.bus.broker().clone();
-                    tokio::spawn(Self::new_connection(broker, stream, addr));
+                    tokio::spawn(Self::new_connection(broker, stream, addr, tls));
                 }
             }
         }
@@ -101,19 +180,83 @@ impl Mainloop {
         Ok(())
     }
 
-    async fn new_connection(handle: BrokerHandle, stream: TcpStream, addr: SocketAddr) {
+    async fn new_connection(
+        handle: BrokerHandle,
+        stream: TcpStream,
+        addr: SocketAddr,
+        tls: Option<TlsAcceptor>,
+    ) {
         log::info!("New connection from {addr}.");
 
-        match Self::new_connection_impl(handle, stream).await {
+        match Self::new_connection_impl(handle, stream, tls).await {
             Ok(()) => log::info!("Connection closed by peer {addr}."),
             Err(e) => log::error!("Connection by peer {addr} failed: {e}."),
         }
     }
 
-    async fn new_connection_impl(mut handle: BrokerHandle, stream: TcpStream) -> Result<()> {
-        let transport = TokioTransport::new(stream);
-        let conn = handle.connect(transport).await?;
-        conn.run().await?;
+    async fn new_connection_impl(
+        mut handle: BrokerHandle,
+        stream: TcpStream,
+        tls: Option<TlsAcceptor>,
+    ) -> Result<()> {
+        match tls {
+            Some(tls) => {
+                let stream = tls
+                    .accept(stream)
+                    .await
+                    .with_context(|| anyhow!("TLS handshake failed"))?;
+
+                let transport = TokioTransport::new(stream);
+                let conn = handle.connect(transport).await?;
+                conn.run().await?;
+            }
+
+            None => {
+                let transport = TokioTransport::new(stream);
+                let conn = handle.connect(transport).await?;
+                conn.run().await?;
+            }
+        }
+
         Ok(())
     }
 }
+
+fn bind(addr: SocketAddr, only_v6: bool) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+
+    if addr.is_ipv6() {
+        socket.set_only_v6(only_v6)?;
+    }
+
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(BACKLOG)?;
+
+    TcpListener::from_std(socket.into())
+}
+
+/// Accepts the next connection on any of the listeners.
+///
+/// Polling starts at `next` and continues after the listener that accepted a connection, so that
+/// a busy listener can't starve the others.
+async fn accept(
+    listeners: &[Listener],
+    next: &mut usize,
+) -> io::Result<(TcpStream, SocketAddr, Option<TlsAcceptor>)> {
+    future::poll_fn(|cx| {
+        for i in 0..listeners.len() {
+            let index = (*next + i) % listeners.len();
+            let listener = &listeners[index];
+
+            if let Poll::Ready(res) = listener.listener.poll_accept(cx) {
+                *next = (index + 1) % listeners.len();
+                return Poll::Ready(res.map(|(stream, addr)| (stream, addr, listener.tls.clone())));
+            }
+        }
+
+        Poll::Pending
+    })
+    .await
+}
@@ -0,0 +1,237 @@
+use crate::schemas::{Share, ShareAccess, SymlinkPolicy};
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+#[derive(Debug, Clone)]
+pub struct ResolvedPath {
+    /// Name of the share.
+    pub share: String,
+    pub root: PathBuf,
+    pub path: PathBuf,
+    pub symlinks: SymlinkPolicy,
+    pub access: ShareAccess,
+}
+
+impl ResolvedPath {
+    pub fn is_share_root(&self) -> bool {
+        self.path == self.root
+    }
+
+    /// Returns the metadata of an entry inside the share, subject to the share's symlink policy.
+    ///
+    /// `Ok(None)` is returned for symbolic links that must not be visible to clients.
+    pub async fn entry_metadata(&self, path: &Path) -> Result<Option<Metadata>> {
+        let metadata = fs::symlink_metadata(path).await?;
+
+        if !metadata.is_symlink() {
+            return Ok(Some(metadata));
+        }
+
+        match self.symlinks {
+            SymlinkPolicy::FollowInside => {
+                let Ok(target) = fs::canonicalize(path).await else {
+                    return Ok(None);
+                };
+
+                if target.starts_with(&self.root) {
+                    fs::metadata(target).await.map(Some).map_err(Into::into)
+                } else {
+                    Ok(None)
+                }
+            }
+
+            SymlinkPolicy::Refuse => Ok(None),
+            SymlinkPolicy::Expose => Ok(Some(metadata)),
+        }
+    }
+}
+
+/// Resolves a path of the form `/share/some/file` to a path on the local file system.
+///
+/// `Ok(None)` refers to the virtual root, which contains all shares.
+pub async fn resolve_path(
+    shares: &RwLock<HashMap<String, Share>>,
+    path: &str,
+) -> Result<Option<ResolvedPath>> {
+    let Some((share, components)) = split_path(shares, path)? else {
+        return Ok(None);
+    };
+
+    let root = share.canonical_root().await?;
+    let path = resolve_symlinks(&root, &components, share.symlinks).await?;
+
+    Ok(Some(ResolvedPath {
+        share: share.name,
+        root,
+        path,
+        symlinks: share.symlinks,
+        access: share.access,
+    }))
+}
+
+/// Resolves the parent directory of a path, which itself need not exist.
+///
+/// Returns the resolved parent and the final component. `Ok(None)` is returned if the path refers
+/// to the virtual root or to the root of a share.
+pub async fn resolve_parent(
+    shares: &RwLock<HashMap<String, Share>>,
+    path: &str,
+) -> Result<Option<(ResolvedPath, String)>> {
+    let Some((share, mut components)) = split_path(shares, path)? else {
+        return Ok(None);
+    };
+
+    let Some(name) = components.pop() else {
+        return Ok(None);
+    };
+
+    let root = share.canonical_root().await?;
+    let parent = resolve_symlinks(&root, &components, share.symlinks).await?;
+
+    if !fs::symlink_metadata(&parent).await?.is_dir() {
+        return Err(anyhow!("parent is not a directory"));
+    }
+
+    let resolved = ResolvedPath {
+        share: share.name,
+        root,
+        path: parent,
+        symlinks: share.symlinks,
+        access: share.access,
+    };
+
+    Ok(Some((resolved, name.to_owned())))
+}
+
+/// Checks whether a share still exists, is enabled and hasn't expired.
+///
+/// Long-running operations use this to stop once their share goes away.
+pub fn is_share_active(shares: &RwLock<HashMap<String, Share>>, name: &str) -> bool {
+    let now = Utc::now().timestamp_millis();
+
+    match shares.read().get(name) {
+        Some(share) => {
+            !share.disabled.any()
+                && !share
+                    .expires_unix_ms()
+                    .is_some_and(|expires| expires <= now)
+        }
+
+        None => false,
+    }
+}
+
+/// Checks whether a path refers to something below a subdirectory of a drop box.
+///
+/// Drop boxes are flat, so such paths are refused before they are resolved any further.
+pub fn is_in_drop_box_subdirectory(shares: &RwLock<HashMap<String, Share>>, path: &str) -> bool {
+    match split_path(shares, path) {
+        Ok(Some((share, components))) => {
+            (share.access == ShareAccess::DropBox) && (components.len() > 1)
+        }
+
+        _ => false,
+    }
+}
+
+struct ShareRoot {
+    name: String,
+    root: PathBuf,
+    symlinks: SymlinkPolicy,
+    access: ShareAccess,
+}
+
+impl ShareRoot {
+    async fn canonical_root(&self) -> Result<PathBuf> {
+        fs::canonicalize(&self.root)
+            .await
+            .with_context(|| anyhow!("failed to resolve share root `{}`", self.root.display()))
+    }
+}
+
+/// Splits a path into its share and the normalized components inside the share.
+fn split_path<'a>(
+    shares: &RwLock<HashMap<String, Share>>,
+    path: &'a str,
+) -> Result<Option<(ShareRoot, Vec<&'a str>)>> {
+    let mut share = None;
+    let mut components = Vec::new();
+
+    for component in path.split('/') {
+        if component.is_empty() || (component == ".") {
+            continue;
+        }
+
+        if share.is_some() {
+            if component == ".." {
+                // Never leave the share's root directory.
+                components.pop();
+            } else if component.contains('\0') {
+                return Err(anyhow!("path contains a NUL character"));
+            } else {
+                components.push(component);
+            }
+        } else if component != ".." {
+            let shares = shares.read();
+            let Some(found) = shares.get(component) else {
+                return Err(anyhow!("unknown share `{component}`"));
+            };
+
+            if found.disabled.any() {
+                return Err(anyhow!("share `{component}` is disabled"));
+            }
+
+            share = Some(ShareRoot {
+                name: found.name.clone(),
+                root: PathBuf::from(&found.path),
+                symlinks: found.symlinks,
+                access: found.access,
+            });
+        }
+    }
+
+    Ok(share.map(|share| (share, components)))
+}
+
+async fn resolve_symlinks(
+    root: &Path,
+    components: &[&str],
+    symlinks: SymlinkPolicy,
+) -> Result<PathBuf> {
+    if symlinks == SymlinkPolicy::FollowInside {
+        let mut path = root.to_owned();
+        path.extend(components);
+
+        let path = fs::canonicalize(&path).await?;
+
+        if !path.starts_with(root) {
+            return Err(anyhow!("path leads outside of the share"));
+        }
+
+        return Ok(path);
+    }
+
+    let mut path = root.to_owned();
+
+    for (i, component) in components.iter().enumerate() {
+        path.push(component);
+
+        if !fs::symlink_metadata(&path).await?.is_symlink() {
+            continue;
+        }
+
+        // Exposed symbolic links may only appear as the last component, because they are never
+        // followed.
+        let last = i + 1 == components.len();
+        if (symlinks != SymlinkPolicy::Expose) || !last {
+            return Err(anyhow!("path contains a symbolic link"));
+        }
+    }
+
+    Ok(path)
+}
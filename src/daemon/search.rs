@@ -0,0 +1,225 @@
+use super::resolve::ResolvedPath;
+use super::wily_calls;
+use crate::schemas::{FileType, Metadata, PatternType, SearchMatch, WilySearchArgs};
+use aldrin::Sender;
+use anyhow::Result;
+use globset::{GlobBuilder, GlobMatcher};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+const DEFAULT_LIMIT: u32 = 1000;
+pub(super) const MAX_LIMIT: u32 = 100_000;
+const MAX_DEPTH: u32 = 64;
+
+/// A search for files below a directory inside a share.
+pub struct Search {
+    pattern: Pattern,
+    file_type: Option<FileType>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    modified_after_unix_ms: Option<i64>,
+    modified_before_unix_ms: Option<i64>,
+    max_depth: u32,
+    limit: u32,
+}
+
+enum Pattern {
+    Glob {
+        matcher: GlobMatcher,
+        match_path: bool,
+    },
+
+    Substring {
+        needle: String,
+        ignore_case: bool,
+    },
+}
+
+impl Search {
+    /// Creates a new search. Returns an error message if the pattern is invalid.
+    pub fn new(args: &WilySearchArgs) -> Result<Self, String> {
+        let ignore_case = args.ignore_case.unwrap_or(false);
+
+        let pattern = match args.pattern_type.unwrap_or_default() {
+            PatternType::Glob => {
+                let glob = GlobBuilder::new(&args.pattern)
+                    .case_insensitive(ignore_case)
+                    .literal_separator(true)
+                    .build()
+                    .map_err(|e| e.kind().to_string())?;
+
+                Pattern::Glob {
+                    matcher: glob.compile_matcher(),
+                    match_path: args.pattern.contains('/'),
+                }
+            }
+
+            PatternType::Substring => Pattern::Substring {
+                needle: if ignore_case {
+                    args.pattern.to_lowercase()
+                } else {
+                    args.pattern.clone()
+                },
+                ignore_case,
+            },
+        };
+
+        Ok(Self {
+            pattern,
+            file_type: args.file_type,
+            min_size: args.min_size,
+            max_size: args.max_size,
+            modified_after_unix_ms: args.modified_after_unix_ms,
+            modified_before_unix_ms: args.modified_before_unix_ms,
+            max_depth: args.max_depth.unwrap_or(MAX_DEPTH).min(MAX_DEPTH),
+            limit: args.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT),
+        })
+    }
+
+    /// Walks the directory `dir` breadth-first and sends all matches.
+    ///
+    /// Paths of matches are reported relative to `base`, the path that was searched. Returns the
+    /// number of matches.
+    pub async fn run(
+        &self,
+        dir: &ResolvedPath,
+        base: &str,
+        sender: &mut Sender<SearchMatch>,
+    ) -> Result<u32> {
+        let base = base.trim_end_matches('/');
+        let mut pending = VecDeque::from([(PathBuf::new(), 1)]);
+        let mut found = 0;
+
+        while let Some((rel, depth)) = pending.pop_front() {
+            let path = dir.path.join(&rel);
+
+            let Ok(mut entries) = fs::read_dir(&path).await else {
+                continue;
+            };
+
+            let mut names = Vec::new();
+            while let Some(entry) = entries.next_entry().await? {
+                if let Ok(name) = entry.file_name().into_string() {
+                    names.push(name);
+                }
+            }
+
+            names.sort_unstable();
+
+            for name in names {
+                let path = path.join(&name);
+                let rel = rel.join(&name);
+
+                let Ok(Some(metadata)) = dir.entry_metadata(&path).await else {
+                    continue;
+                };
+
+                // Devices, sockets and FIFOs are never reported. Only directories are ever opened
+                // during a search, and `file_metadata` doesn't open anything either.
+                let file_type = metadata.file_type();
+                if !file_type.is_file() && !file_type.is_dir() && !file_type.is_symlink() {
+                    continue;
+                }
+
+                // Never descend into directories behind symbolic links, which could form loops.
+                let is_dir = metadata.is_dir();
+                let is_link = fs::symlink_metadata(&path)
+                    .await
+                    .is_ok_and(|metadata| metadata.is_symlink());
+
+                if is_dir && !is_link && (depth < self.max_depth) {
+                    pending.push_back((rel.clone(), depth + 1));
+                }
+
+                if !self.matches_name(&name, &rel) {
+                    continue;
+                }
+
+                let metadata = wily_calls::file_metadata(&path, &metadata).await;
+                if !self.matches_metadata(&metadata) {
+                    continue;
+                }
+
+                sender
+                    .send_item(&SearchMatch {
+                        path: format!("{base}/{}", rel.to_string_lossy()),
+                        metadata,
+                    })
+                    .await?;
+
+                found += 1;
+                if found >= self.limit {
+                    return Ok(found);
+                }
+            }
+        }
+
+        Ok(found)
+    }
+
+    fn matches_name(&self, name: &str, rel: &Path) -> bool {
+        match self.pattern {
+            Pattern::Glob {
+                ref matcher,
+                match_path: true,
+            } => matcher.is_match(rel),
+
+            Pattern::Glob {
+                ref matcher,
+                match_path: false,
+            } => matcher.is_match(name),
+
+            Pattern::Substring {
+                ref needle,
+                ignore_case: true,
+            } => name.to_lowercase().contains(needle),
+
+            Pattern::Substring {
+                ref needle,
+                ignore_case: false,
+            } => name.contains(needle),
+        }
+    }
+
+    fn matches_metadata(&self, metadata: &Metadata) -> bool {
+        if self
+            .file_type
+            .is_some_and(|file_type| file_type != metadata.file_type)
+        {
+            return false;
+        }
+
+        // Size filters only make sense for files.
+        if (self.min_size.is_some() || self.max_size.is_some())
+            && (metadata.file_type != FileType::File)
+        {
+            return false;
+        }
+
+        let size = metadata.size.unwrap_or(0);
+        if self.min_size.is_some_and(|min| size < min)
+            || self.max_size.is_some_and(|max| size > max)
+        {
+            return false;
+        }
+
+        if self.modified_after_unix_ms.is_some() || self.modified_before_unix_ms.is_some() {
+            let Some(modified) = metadata.modified_unix_ms else {
+                return false;
+            };
+
+            if self
+                .modified_after_unix_ms
+                .is_some_and(|after| modified < after)
+                || self
+                    .modified_before_unix_ms
+                    .is_some_and(|before| modified > before)
+            {
+                return false;
+            }
+        }
+
+        true
+    }
+}
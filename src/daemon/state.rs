@@ -0,0 +1,142 @@
+use super::config::{ShareAccessDef, SymlinkPolicyDef};
+use crate::schemas::{PersistedShare, Share, ShareAccess, ShareDisabled, ShareType, SymlinkPolicy};
+use crate::utils;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+const STATE_FILE: &str = "shares.toml";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    #[serde(default, rename = "share")]
+    shares: Vec<StateShare>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StateShare {
+    name: String,
+    path: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_unix_ms: Option<i64>,
+
+    #[serde(default)]
+    disabled: bool,
+
+    #[serde(default, with = "SymlinkPolicyDef")]
+    symlinks: SymlinkPolicy,
+
+    #[serde(default, with = "ShareAccessDef")]
+    access: ShareAccess,
+}
+
+impl StateShare {
+    fn from_share(share: &Share) -> Option<Self> {
+        let ShareType::Persisted(ref persisted) = share.share_type else {
+            return None;
+        };
+
+        Some(Self {
+            name: share.name.clone(),
+            path: share.path.clone(),
+            expires_unix_ms: persisted.expires_unix_ms,
+            disabled: share.disabled.user,
+            symlinks: share.symlinks,
+            access: share.access,
+        })
+    }
+
+    fn into_share(self) -> Share {
+        Share {
+            name: self.name,
+            path: self.path,
+            share_type: ShareType::Persisted(PersistedShare {
+                expires_unix_ms: self.expires_unix_ms,
+            }),
+            disabled: ShareDisabled {
+                user: self.disabled,
+            },
+            symlinks: self.symlinks,
+            access: self.access,
+        }
+    }
+}
+
+pub fn default_path() -> Result<PathBuf> {
+    let mut path = utils::data_dir()?;
+    path.push(STATE_FILE);
+    Ok(path)
+}
+
+pub fn load(path: &Path) -> Result<Vec<Share>> {
+    log::debug!("Loading persisted shares from `{}`.", path.display());
+
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(e).with_context(|| anyhow!("failed to read `{}`", path.display()));
+        }
+    };
+
+    let state: State = toml::from_str(&contents)
+        .with_context(|| anyhow!("failed to parse `{}`", path.display()))?;
+
+    Ok(state
+        .shares
+        .into_iter()
+        .map(StateShare::into_share)
+        .collect())
+}
+
+/// Saves all persisted shares in `shares` and `shadowed`.
+///
+/// `shadowed` are persisted shares that are currently not active, because a static share of the
+/// same name exists.
+pub fn save(path: &Path, shares: &[Share], shadowed: &[Share]) -> Result<()> {
+    log::debug!("Saving persisted shares to `{}`.", path.display());
+
+    let mut state = State {
+        shares: shares
+            .iter()
+            .chain(shadowed)
+            .filter_map(StateShare::from_share)
+            .collect(),
+    };
+    state.shares.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+
+    let contents =
+        toml::to_string(&state).with_context(|| anyhow!("failed to serialize persisted shares"))?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| anyhow!("failed to create directory `{}`", dir.display()))?;
+    }
+
+    // Write to a temporary file first and then atomically replace the old state, so that a crash
+    // can never leave a partially written file behind.
+    let mut tmp_path = OsString::from(path);
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let mut file = File::create(&tmp_path)
+        .with_context(|| anyhow!("failed to create `{}`", tmp_path.display()))?;
+
+    file.write_all(contents.as_bytes())
+        .and_then(|()| file.sync_all())
+        .with_context(|| anyhow!("failed to write `{}`", tmp_path.display()))?;
+
+    fs::rename(&tmp_path, path).with_context(|| {
+        anyhow!(
+            "failed to rename `{}` to `{}`",
+            tmp_path.display(),
+            path.display()
+        )
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,135 @@
+use crate::{tls, utils};
+use anyhow::{anyhow, Context, Result};
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+const TLS_DIR: &str = "tls";
+const CERT_FILE: &str = "cert.pem";
+const KEY_FILE: &str = "key.pem";
+
+/// Creates the TLS acceptor for public connections.
+///
+/// Without an explicit certificate and key, a self-signed certificate for `hostname` is generated
+/// on first use and kept in the data directory.
+pub fn acceptor(cert: Option<&Path>, key: Option<&Path>, hostname: &str) -> Result<TlsAcceptor> {
+    let (cert_path, key_path) = match (cert, key) {
+        (Some(cert), Some(key)) => (cert.to_owned(), key.to_owned()),
+
+        (None, None) => {
+            let (cert, key) = default_paths()?;
+
+            if !cert.exists() || !key.exists() {
+                generate(&cert, &key, hostname)?;
+            }
+
+            (cert, key)
+        }
+
+        _ => {
+            return Err(anyhow!(
+                "a TLS certificate requires a private key and vice versa"
+            ))
+        }
+    };
+
+    let certs = load_certs(&cert_path)?;
+    let key = load_key(&key_path)?;
+
+    log::info!(
+        "Using TLS certificate `{}` with fingerprint {}.",
+        cert_path.display(),
+        tls::fingerprint(&certs[0]),
+    );
+
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .with_context(|| anyhow!("invalid TLS certificate `{}`", cert_path.display()))?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+fn default_paths() -> Result<(PathBuf, PathBuf)> {
+    let mut dir = utils::data_dir()?;
+    dir.push(TLS_DIR);
+    Ok((dir.join(CERT_FILE), dir.join(KEY_FILE)))
+}
+
+fn generate(cert_path: &Path, key_path: &Path, hostname: &str) -> Result<()> {
+    log::info!("Generating a self-signed TLS certificate for `{hostname}`.");
+
+    let names = vec![hostname.to_owned(), "localhost".to_owned()];
+    let cert = rcgen::generate_simple_self_signed(names)
+        .with_context(|| anyhow!("failed to generate a TLS certificate"))?;
+
+    let cert_pem = cert
+        .serialize_pem()
+        .with_context(|| anyhow!("failed to serialize the TLS certificate"))?;
+    let key_pem = cert.serialize_private_key_pem();
+
+    if let Some(dir) = key_path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| anyhow!("failed to create directory `{}`", dir.display()))?;
+    }
+
+    // Only the owner may ever read the private key. The mode only applies to new files, so the
+    // key is written to a new file first, which then replaces any existing one.
+    let mut tmp_path = OsString::from(key_path);
+    tmp_path.push(format!(".{}.tmp", process::id()));
+    let tmp_path = PathBuf::from(tmp_path);
+
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&tmp_path)
+        .and_then(|mut file| file.write_all(key_pem.as_bytes()))
+        .with_context(|| anyhow!("failed to write `{}`", tmp_path.display()))?;
+
+    if let Err(e) = fs::rename(&tmp_path, key_path) {
+        let _ = fs::remove_file(&tmp_path);
+
+        return Err(e).with_context(|| {
+            anyhow!(
+                "failed to rename `{}` to `{}`",
+                tmp_path.display(),
+                key_path.display()
+            )
+        });
+    }
+
+    fs::write(cert_path, cert_pem)
+        .with_context(|| anyhow!("failed to write `{}`", cert_path.display()))?;
+
+    Ok(())
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let file = File::open(path).with_context(|| anyhow!("failed to open `{}`", path.display()))?;
+
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| anyhow!("failed to parse `{}`", path.display()))?;
+
+    if certs.is_empty() {
+        return Err(anyhow!("no certificate found in `{}`", path.display()));
+    }
+
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    let file = File::open(path).with_context(|| anyhow!("failed to open `{}`", path.display()))?;
+
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .with_context(|| anyhow!("failed to parse `{}`", path.display()))?
+        .ok_or_else(|| anyhow!("no private key found in `{}`", path.display()))
+}
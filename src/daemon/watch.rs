@@ -0,0 +1,336 @@
+use super::resolve::ResolvedPath;
+use crate::schemas::{WatchEvent, WatchRename};
+use futures::StreamExt;
+use inotify::{Event, EventMask, EventStream, Inotify, WatchDescriptor, WatchMask};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::fs;
+
+const MAX_WATCHES: usize = 8192;
+const EVENT_BUFFER_SIZE: usize = 16 * 1024;
+const MODIFIED_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Translates inotify events of a watched path inside a share into `WatchEvent`s.
+pub struct Watch {
+    events: EventStream<Vec<u8>>,
+    resolved: ResolvedPath,
+    base: String,
+    recursive: bool,
+    dirs: HashMap<WatchDescriptor, PathBuf>,
+    pending_move: Option<(u32, PathBuf)>,
+    modified: HashMap<PathBuf, Instant>,
+    done: bool,
+}
+
+impl Watch {
+    /// Starts watching `resolved`, which is reported to clients as `base`.
+    pub async fn new(resolved: ResolvedPath, base: &str, recursive: bool) -> io::Result<Self> {
+        let inotify = Inotify::init()?;
+        let events = inotify.into_event_stream(vec![0; EVENT_BUFFER_SIZE])?;
+
+        let mut this = Self {
+            events,
+            resolved,
+            base: base.trim_end_matches('/').to_owned(),
+            recursive,
+            dirs: HashMap::new(),
+            pending_move: None,
+            modified: HashMap::new(),
+            done: false,
+        };
+
+        let wd = this
+            .events
+            .watches()
+            .add(&this.resolved.path, watch_mask())?;
+        this.dirs.insert(wd, PathBuf::new());
+
+        if recursive && fs::symlink_metadata(&this.resolved.path).await?.is_dir() {
+            this.add_subdirs(PathBuf::new()).await;
+        }
+
+        Ok(this)
+    }
+
+    /// Waits for the next batch of events.
+    ///
+    /// `Ok(None)` is returned when the watched path has been removed.
+    pub async fn next(&mut self) -> io::Result<Option<Vec<WatchEvent>>> {
+        while !self.done {
+            let Some(event) = self.events.next().await else {
+                break;
+            };
+
+            let events = self.handle(event?).await;
+            if !events.is_empty() {
+                return Ok(Some(events));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Reports a pending move as a deletion, if its target never showed up.
+    pub fn flush(&mut self) -> Option<WatchEvent> {
+        let (_, from) = self.pending_move.take()?;
+        Some(self.moved_out(&from))
+    }
+
+    async fn handle(&mut self, event: Event<OsString>) -> Vec<WatchEvent> {
+        let mut events = Vec::new();
+
+        if event.mask.contains(EventMask::Q_OVERFLOW) {
+            events.push(WatchEvent::Overflow);
+            return events;
+        }
+
+        let Some(dir) = self.dirs.get(&event.wd).cloned() else {
+            return events;
+        };
+
+        if event.mask.contains(EventMask::IGNORED) {
+            self.dirs.remove(&event.wd);
+            self.done = self.dirs.is_empty();
+            return events;
+        }
+
+        // Removal of subdirectories is reported by their parent.
+        if event
+            .mask
+            .intersects(EventMask::DELETE_SELF | EventMask::MOVE_SELF)
+        {
+            if dir.as_os_str().is_empty() {
+                events.extend(self.flush());
+                events.push(WatchEvent::Deleted(self.base.clone()));
+                self.done = true;
+            }
+
+            return events;
+        }
+
+        let path = match event.name {
+            Some(ref name) if is_upload(name) => return events,
+            Some(ref name) => dir.join(name),
+            None => dir,
+        };
+
+        if let Some((cookie, from)) = self.pending_move.take() {
+            if event.mask.contains(EventMask::MOVED_TO) && (event.cookie == cookie) {
+                self.renamed(&from, &path);
+
+                events.push(WatchEvent::Renamed(WatchRename {
+                    from: self.wily_path(&from),
+                    to: self.wily_path(&path),
+                }));
+
+                return events;
+            }
+
+            events.push(self.moved_out(&from));
+        }
+
+        if event.mask.contains(EventMask::MOVED_FROM) {
+            // Pairs of moves are matched by their cookie.
+            self.pending_move = Some((event.cookie, path));
+        } else if event
+            .mask
+            .intersects(EventMask::CREATE | EventMask::MOVED_TO)
+        {
+            if self.is_visible(&path).await {
+                events.push(WatchEvent::Created(self.wily_path(&path)));
+
+                if self.recursive && event.mask.contains(EventMask::ISDIR) {
+                    self.add_subdirs(path).await;
+                }
+            }
+        } else if event.mask.contains(EventMask::DELETE) {
+            events.push(WatchEvent::Deleted(self.wily_path(&path)));
+        } else if event.mask.contains(EventMask::CLOSE_WRITE) {
+            // The end of a write is always reported, so that clients see the final contents.
+            self.modified.remove(&path);
+
+            if self.is_visible(&path).await {
+                events.push(WatchEvent::Modified(self.wily_path(&path)));
+            }
+        } else if event.mask.contains(EventMask::MODIFY) {
+            if self.debounce_modified(&path) && self.is_visible(&path).await {
+                events.push(WatchEvent::Modified(self.wily_path(&path)));
+            }
+        }
+
+        events
+    }
+
+    /// Checks whether a modification of `path` should be reported.
+    ///
+    /// Writes usually arrive in bursts, so modifications are reported at most once per
+    /// `MODIFIED_INTERVAL` for each path. This does not apply to closing a file after writing.
+    fn debounce_modified(&mut self, path: &Path) -> bool {
+        let now = Instant::now();
+        self.modified
+            .retain(|_, reported| now.duration_since(*reported) < MODIFIED_INTERVAL);
+
+        if self.modified.contains_key(path) {
+            false
+        } else {
+            self.modified.insert(path.to_owned(), now);
+            true
+        }
+    }
+
+    /// Adds watches for `dir` and all directories below it.
+    async fn add_subdirs(&mut self, dir: PathBuf) {
+        let mut pending = vec![dir];
+
+        while let Some(dir) = pending.pop() {
+            let path = self.resolved.path.join(&dir);
+
+            if !dir.as_os_str().is_empty() {
+                if self.dirs.len() >= MAX_WATCHES {
+                    log::warn!("Not watching `{}`: too many watches.", path.display());
+                    return;
+                }
+
+                match self.events.watches().add(&path, watch_mask()) {
+                    Ok(wd) => {
+                        self.dirs.insert(wd, dir.clone());
+                    }
+
+                    Err(e) => {
+                        log::warn!("Failed to watch `{}`: {e}.", path.display());
+                        continue;
+                    }
+                }
+            }
+
+            let Ok(mut entries) = fs::read_dir(&path).await else {
+                continue;
+            };
+
+            // Symbolic links to directories are never followed.
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                if entry.file_type().await.is_ok_and(|t| t.is_dir()) {
+                    pending.push(dir.join(entry.file_name()));
+                }
+            }
+        }
+    }
+
+    /// Updates the watched directories after a rename.
+    fn renamed(&mut self, from: &Path, to: &Path) {
+        for dir in self.dirs.values_mut() {
+            if let Ok(rest) = dir.strip_prefix(from) {
+                *dir = to.join(rest);
+            }
+        }
+    }
+
+    /// Stops watching directories that were moved out of the watched tree.
+    fn moved_out(&mut self, from: &Path) -> WatchEvent {
+        let moved: Vec<_> = self
+            .dirs
+            .iter()
+            .filter(|(_, dir)| dir.starts_with(from))
+            .map(|(wd, _)| wd.clone())
+            .collect();
+
+        for wd in moved {
+            self.dirs.remove(&wd);
+            let _ = self.events.watches().remove(wd);
+        }
+
+        WatchEvent::Deleted(self.wily_path(from))
+    }
+
+    async fn is_visible(&self, path: &Path) -> bool {
+        // Joining an empty path would append a trailing slash, which fails for files.
+        let path = if path.as_os_str().is_empty() {
+            self.resolved.path.clone()
+        } else {
+            self.resolved.path.join(path)
+        };
+
+        matches!(self.resolved.entry_metadata(&path).await, Ok(Some(_)))
+    }
+
+    fn wily_path(&self, path: &Path) -> String {
+        if path.as_os_str().is_empty() {
+            self.base.clone()
+        } else {
+            format!("{}/{}", self.base, path.to_string_lossy())
+        }
+    }
+}
+
+fn watch_mask() -> WatchMask {
+    WatchMask::CREATE
+        | WatchMask::DELETE
+        | WatchMask::MODIFY
+        | WatchMask::CLOSE_WRITE
+        | WatchMask::MOVED_FROM
+        | WatchMask::MOVED_TO
+        | WatchMask::DELETE_SELF
+        | WatchMask::MOVE_SELF
+        | WatchMask::DONT_FOLLOW
+}
+
+/// Checks whether a name belongs to a temporary file of an upload in progress.
+fn is_upload(name: &OsString) -> bool {
+    name.to_string_lossy().contains(".wily-upload-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schemas::{ShareAccess, SymlinkPolicy};
+    use tokio::time;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    async fn next_modified(watch: &mut Watch) -> String {
+        loop {
+            let events = time::timeout(TIMEOUT, watch.next())
+                .await
+                .expect("no event received")
+                .unwrap()
+                .expect("watch ended");
+
+            for event in events {
+                if let WatchEvent::Modified(path) = event {
+                    return path;
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn single_file() {
+        let root = std::env::temp_dir().join(format!("wily-watch-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&root).unwrap();
+        let file = root.join("file.log");
+        std::fs::write(&file, b"foo").unwrap();
+
+        let resolved = ResolvedPath {
+            share: "share".to_owned(),
+            root: root.clone(),
+            path: file.clone(),
+            symlinks: SymlinkPolicy::Refuse,
+            access: ShareAccess::ReadOnly,
+        };
+
+        let mut watch = Watch::new(resolved, "/share/file.log", false)
+            .await
+            .unwrap();
+
+        // Both writes fall into the same interval, but closing the file is always reported.
+        std::fs::write(&file, b"bar").unwrap();
+        assert_eq!(next_modified(&mut watch).await, "/share/file.log");
+        std::fs::write(&file, b"baz").unwrap();
+        assert_eq!(next_modified(&mut watch).await, "/share/file.log");
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
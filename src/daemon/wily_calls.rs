@@ -1,16 +1,64 @@
+use super::archive;
+use super::hash_cache::HashCache;
+use super::resolve::{self, ResolvedPath};
+use super::search::{self, Search};
+use super::watch::Watch;
 use super::Mainloop;
-use crate::schemas::{Metadata, Share, WilyFunction, WilyQueryArgs, WilyQueryError, WilyQueryOk};
-use aldrin::Promise;
-use anyhow::{anyhow, Result};
-use parking_lot::RwLock;
-use std::collections::HashMap;
-use std::path::{Component, Path, PathBuf};
+use crate::schemas::{
+    features, ArchiveChunk, DirEntry, FileType, Identity, Metadata, ServerInfo, Share, ShareAccess,
+    WatchEvent, WilyArchiveArgs, WilyArchiveError, WilyDeltaArgs, WilyDeltaError, WilyDeltaOk,
+    WilyFunction, WilyHashArgs, WilyHashError, WilyHashOk, WilyListArgs, WilyListError, WilyListOk,
+    WilyMkdirArgs, WilyMkdirError, WilyQueryArgs, WilyQueryError, WilyQueryOk, WilyReadArgs,
+    WilyReadError, WilyReadOk, WilyRemoveArgs, WilyRemoveError, WilyRenameArgs, WilyRenameError,
+    WilySearchArgs, WilySearchError, WilyWatchArgs, WilyWatchError, WilyWriteArgs, WilyWriteError,
+    WilyWriteOk, WILY_PROTOCOL,
+};
+use crate::{delta, utils};
+use aldrin::core::Bytes;
+use aldrin::{Handle as ClientHandle, Promise, Sender, UnboundReceiver};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use parking_lot::{Mutex, RwLock};
+use rustix::fs::{Access, RenameFlags, CWD};
+use rustix::io::Errno;
+use std::collections::{BinaryHeap, HashMap};
+use std::convert::Infallible;
+use std::fs;
+use std::io::{self, ErrorKind, Read, SeekFrom};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs::{self as async_fs, File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+const DEFAULT_LIST_LIMIT: u32 = 256;
+const MAX_LIST_LIMIT: u32 = 1024;
+const CHUNK_SIZE: usize = 64 * 1024;
+const CHANNEL_CAPACITY: u32 = 16;
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
+const SHARE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
 
 impl Mainloop {
     pub(super) fn wily_call(&self, call: WilyFunction) {
         match call {
             WilyFunction::Query(args, promise) => self.wily_query(args, promise),
+            WilyFunction::List(args, promise) => self.wily_list(args, promise),
+            WilyFunction::Read(args, promise) => self.wily_read(args, promise),
+            WilyFunction::Write(args, promise) => self.wily_write(args, promise),
+            WilyFunction::Mkdir(args, promise) => self.wily_mkdir(args, promise),
+            WilyFunction::Rename(args, promise) => self.wily_rename(args, promise),
+            WilyFunction::Remove(args, promise) => self.wily_remove(args, promise),
+            WilyFunction::Archive(args, promise) => self.wily_archive(args, promise),
+            WilyFunction::Hash(args, promise) => self.wily_hash(args, promise),
+            WilyFunction::Delta(args, promise) => self.wily_delta(args, promise),
+            WilyFunction::Watch(args, promise) => self.wily_watch(args, promise),
+            WilyFunction::Search(args, promise) => self.wily_search(args, promise),
+            WilyFunction::ServerInfo(promise) => self.wily_server_info(promise),
+            WilyFunction::Identity(promise) => self.wily_identity(promise),
         }
     }
 
@@ -24,8 +72,8 @@ impl Mainloop {
         promise: Promise<WilyQueryOk, WilyQueryError>,
         shares: Arc<RwLock<HashMap<String, Share>>>,
     ) -> Result<()> {
-        let path = match Self::resolve_path(&shares, &args.path).await {
-            Ok(path) => path,
+        let resolved = match resolve::resolve_path(&shares, &args.path).await {
+            Ok(resolved) => resolved,
 
             Err(e) => {
                 log::error!("Failed to query `{}`: {}.", args.path, e);
@@ -34,8 +82,26 @@ impl Mainloop {
             }
         };
 
-        match path {
-            Some(path) => todo!(),
+        match resolved {
+            // Only the root of a drop box may be queried, so that clients can learn about it.
+            Some(resolved)
+                if (resolved.access == ShareAccess::DropBox) && !resolved.is_share_root() =>
+            {
+                log::error!("Failed to query `{}`: share is a drop box.", args.path);
+                promise.err(&WilyQueryError::PermissionDenied)?;
+            }
+
+            Some(resolved) => match async_fs::symlink_metadata(&resolved.path).await {
+                Ok(metadata) => {
+                    let metadata = file_metadata(&resolved.path, &metadata).await;
+                    promise.ok(&WilyQueryOk::Metadata(metadata))?;
+                }
+
+                Err(e) => {
+                    log::error!("Failed to query `{}`: {}.", args.path, e);
+                    promise.err(&WilyQueryError::FileNotFound)?;
+                }
+            },
 
             None => {
                 promise.ok(&WilyQueryOk::Root)?;
@@ -45,37 +111,1491 @@ impl Mainloop {
         Ok(())
     }
 
-    async fn resolve_path(
-        shares: &Arc<RwLock<HashMap<String, Share>>>,
-        path: &str,
-    ) -> Result<Option<PathBuf>> {
-        let mut resolved = None;
+    fn wily_list(&self, args: WilyListArgs, promise: Promise<WilyListOk, WilyListError>) {
+        log::info!("Listing path `{}`.", args.path);
+        tokio::spawn(Self::wily_list_impl(args, promise, self.shares.clone()));
+    }
+
+    async fn wily_list_impl(
+        args: WilyListArgs,
+        promise: Promise<WilyListOk, WilyListError>,
+        shares: Arc<RwLock<HashMap<String, Share>>>,
+    ) -> Result<()> {
+        let limit = args
+            .limit
+            .unwrap_or(DEFAULT_LIST_LIMIT)
+            .clamp(1, MAX_LIST_LIMIT) as usize;
+
+        let resolved = match resolve::resolve_path(&shares, &args.path).await {
+            Ok(Some(resolved)) => resolved,
+
+            Ok(None) => {
+                let list = list_root(&shares, args.cursor.as_deref(), limit).await;
+                promise.ok(&list)?;
+                return Ok(());
+            }
+
+            Err(e) => {
+                log::error!("Failed to list `{}`: {}.", args.path, e);
+                promise.err(&WilyListError::FileNotFound)?;
+                return Ok(());
+            }
+        };
+
+        if resolved.access == ShareAccess::DropBox {
+            log::error!("Failed to list `{}`: share is a drop box.", args.path);
+            promise.err(&WilyListError::PermissionDenied)?;
+            return Ok(());
+        }
+
+        match async_fs::symlink_metadata(&resolved.path).await {
+            Ok(metadata) if metadata.is_dir() => {}
+
+            Ok(_) => {
+                log::error!("Failed to list `{}`: not a directory.", args.path);
+                promise.err(&WilyListError::NotADirectory)?;
+                return Ok(());
+            }
+
+            Err(e) => {
+                log::error!("Failed to list `{}`: {}.", args.path, e);
+                promise.err(&WilyListError::FileNotFound)?;
+                return Ok(());
+            }
+        }
+
+        match list_directory(&resolved, args.cursor.as_deref(), limit).await {
+            Ok(list) => promise.ok(&list)?,
+
+            Err(e) => {
+                log::error!("Failed to list `{}`: {}.", args.path, e);
+                promise.err(&WilyListError::FileNotFound)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn wily_read(&self, args: WilyReadArgs, promise: Promise<WilyReadOk, WilyReadError>) {
+        log::info!("Reading path `{}`.", args.path);
+
+        let client = ClientHandle::clone(&self.public_bus);
+        tokio::spawn(Self::wily_read_impl(
+            args,
+            promise,
+            self.shares.clone(),
+            client,
+        ));
+    }
+
+    async fn wily_read_impl(
+        args: WilyReadArgs,
+        promise: Promise<WilyReadOk, WilyReadError>,
+        shares: Arc<RwLock<HashMap<String, Share>>>,
+        client: ClientHandle,
+    ) -> Result<()> {
+        let resolved = match resolve::resolve_path(&shares, &args.path).await {
+            Ok(Some(resolved)) => resolved,
+
+            Ok(None) => {
+                log::error!("Failed to read `{}`: not a file.", args.path);
+                promise.err(&WilyReadError::NotAFile)?;
+                return Ok(());
+            }
+
+            Err(e) => {
+                log::error!("Failed to read `{}`: {}.", args.path, e);
+                promise.err(&WilyReadError::FileNotFound)?;
+                return Ok(());
+            }
+        };
+
+        if resolved.access == ShareAccess::DropBox {
+            log::error!("Failed to read `{}`: share is a drop box.", args.path);
+            promise.err(&WilyReadError::PermissionDenied)?;
+            return Ok(());
+        }
+
+        // Check the type before opening the file, because opening would follow exposed symbolic
+        // links.
+        let metadata = match async_fs::symlink_metadata(&resolved.path).await {
+            Ok(metadata) if metadata.is_file() => metadata,
+
+            Ok(_) => {
+                log::error!("Failed to read `{}`: not a file.", args.path);
+                promise.err(&WilyReadError::NotAFile)?;
+                return Ok(());
+            }
+
+            Err(e) => {
+                log::error!("Failed to read `{}`: {}.", args.path, e);
+                promise.err(&WilyReadError::FileNotFound)?;
+                return Ok(());
+            }
+        };
+
+        let size = metadata.len();
+        let modified_unix_ms = modified_unix_ms(&metadata);
+
+        if let Some(ref if_unchanged) = args.if_unchanged {
+            if (if_unchanged.size != size) || (if_unchanged.modified_unix_ms != modified_unix_ms) {
+                log::error!("Failed to read `{}`: file was modified.", args.path);
+                promise.err(&WilyReadError::Modified)?;
+                return Ok(());
+            }
+        }
+
+        let offset = args.offset.unwrap_or(0);
+        if offset > size {
+            log::error!(
+                "Failed to read `{}`: offset {offset} is out of range.",
+                args.path
+            );
+            promise.err(&WilyReadError::InvalidRange)?;
+            return Ok(());
+        }
+
+        let mut file = match File::open(&resolved.path).await {
+            Ok(file) => file,
+
+            Err(e) => {
+                log::error!("Failed to read `{}`: {}.", args.path, e);
+                promise.err(&WilyReadError::FileNotFound)?;
+                return Ok(());
+            }
+        };
+
+        if offset > 0 {
+            file.seek(SeekFrom::Start(offset)).await?;
+        }
+
+        let mut sender = args.sender.bind(client).claim().await?;
+
+        promise.ok(&WilyReadOk {
+            size,
+            modified_unix_ms,
+        })?;
+
+        // Never send more than announced, even if the file grows in the meantime. Following
+        // continues after that, so the length doesn't apply.
+        let follow = args.follow.unwrap_or(false);
+        let length = if follow {
+            size - offset
+        } else {
+            args.length.unwrap_or(u64::MAX).min(size - offset)
+        };
+
+        let mut file = file.take(length);
+        let mut buf = vec![0; CHUNK_SIZE];
+
+        loop {
+            let len = file.read(&mut buf).await?;
+            if len == 0 {
+                break;
+            }
+
+            sender.send_item(&Bytes::new(&buf[..len])).await?;
+        }
+
+        if follow {
+            let file = file.into_inner();
+            let res = follow_file(
+                file,
+                &shares,
+                &resolved.share,
+                &args.path,
+                offset + length,
+                &mut sender,
+            )
+            .await;
+
+            // Following ends when the share goes away or when the client does.
+            match res {
+                Ok(()) => {
+                    sender.close().await?;
+                    log::info!(
+                        "Stopped following `{}`: share is no longer available.",
+                        args.path
+                    );
+                }
+
+                Err(e) => log::info!("Stopped following `{}`: {}.", args.path, e),
+            }
+
+            return Ok(());
+        }
+
+        sender.close().await?;
+        log::info!("Finished reading `{}`.", args.path);
+
+        Ok(())
+    }
+
+    fn wily_write(&self, args: WilyWriteArgs, promise: Promise<WilyWriteOk, WilyWriteError>) {
+        log::info!("Writing path `{}`.", args.path);
+
+        let client = ClientHandle::clone(&self.public_bus);
+        tokio::spawn(Self::wily_write_impl(
+            args,
+            promise,
+            self.shares.clone(),
+            client,
+        ));
+    }
+
+    async fn wily_write_impl(
+        args: WilyWriteArgs,
+        promise: Promise<WilyWriteOk, WilyWriteError>,
+        shares: Arc<RwLock<HashMap<String, Share>>>,
+        client: ClientHandle,
+    ) -> Result<()> {
+        // Checked before resolving anything, so that errors don't reveal which directories exist.
+        if resolve::is_in_drop_box_subdirectory(&shares, &args.path) {
+            log::error!(
+                "Failed to write `{}`: drop boxes have no subdirectories.",
+                args.path
+            );
+            promise.err(&WilyWriteError::PermissionDenied)?;
+            return Ok(());
+        }
+
+        let (parent, name) = match resolve::resolve_parent(&shares, &args.path).await {
+            Ok(Some(resolved)) => resolved,
+
+            Ok(None) => {
+                log::error!("Failed to write `{}`: not a file.", args.path);
+                promise.err(&WilyWriteError::NotAFile)?;
+                return Ok(());
+            }
+
+            Err(e) => {
+                log::error!("Failed to write `{}`: {}.", args.path, e);
+                promise.err(&WilyWriteError::FileNotFound)?;
+                return Ok(());
+            }
+        };
+
+        if parent.access == ShareAccess::ReadOnly {
+            log::error!("Failed to write `{}`: share is read-only.", args.path);
+            promise.err(&WilyWriteError::ReadOnlyShare)?;
+            return Ok(());
+        }
+
+        // Drop boxes never overwrite existing files. Name collisions are resolved instead.
+        let drop_box = parent.access == ShareAccess::DropBox;
+        let overwrite = !drop_box && args.overwrite.unwrap_or(false);
+        let path = parent.path.join(&name);
+
+        match async_fs::symlink_metadata(&path).await {
+            Ok(metadata) if metadata.is_dir() && !drop_box => {
+                log::error!("Failed to write `{}`: not a file.", args.path);
+                promise.err(&WilyWriteError::NotAFile)?;
+                return Ok(());
+            }
+
+            Ok(_) if !overwrite && !drop_box => {
+                log::error!("Failed to write `{}`: file exists.", args.path);
+                promise.err(&WilyWriteError::AlreadyExists)?;
+                return Ok(());
+            }
+
+            _ => {}
+        }
+
+        // Uploads go to a temporary file first, so that incomplete uploads never replace or
+        // create the target.
+        let tmp_path = parent
+            .path
+            .join(format!(".{name}.wily-upload-{}", Uuid::new_v4()));
+
+        let file = match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp_path)
+            .await
+        {
+            Ok(file) => file,
+
+            Err(e) => {
+                log::error!("Failed to write `{}`: {}.", args.path, e);
+                promise.err(&write_error(&e))?;
+                return Ok(());
+            }
+        };
 
-        for component in path.split('/') {
-            if component.is_empty() || (component == ".") {
-                continue;
+        let size = match receive_file(file, args.receiver, client).await {
+            Ok(size) => size,
+
+            Err(e) => {
+                log::error!("Failed to write `{}`: {}.", args.path, e);
+                remove_tmp_file(&tmp_path).await;
+                promise.err(&WilyWriteError::IoError)?;
+                return Ok(());
             }
+        };
 
-            if let Some(ref mut resolved) = resolved {
-                if component == ".." {
-                    todo!()
+        if args.size.is_some_and(|expected| expected != size) {
+            log::error!("Failed to write `{}`: upload is incomplete.", args.path);
+            remove_tmp_file(&tmp_path).await;
+            promise.err(&WilyWriteError::Incomplete)?;
+            return Ok(());
+        }
+
+        let res = if overwrite {
+            async_fs::rename(&tmp_path, &path)
+                .await
+                .map(|()| name.clone())
+        } else if drop_box {
+            commit_unique(&tmp_path, &parent.path, &name).await
+        } else {
+            commit_upload(&tmp_path, &path).await.map(|()| name.clone())
+        };
+
+        remove_tmp_file(&tmp_path).await;
+
+        match res {
+            Ok(final_name) => {
+                if final_name != name {
+                    log::info!(
+                        "Finished writing `{}` as `{final_name}` ({size} bytes).",
+                        args.path
+                    );
                 } else {
-                    todo!()
+                    log::info!("Finished writing `{}` ({size} bytes).", args.path);
                 }
-            } else {
-                let shares = shares.read();
-                let Some(share) = shares.get(component) else {
-                    return Err(anyhow!("unknown share `{component}`"));
-                };
 
-                if share.disabled.any() {
-                    return Err(anyhow!("share `{component}` is disabled"));
+                promise.ok(&WilyWriteOk {
+                    size,
+                    name: Some(final_name),
+                })?;
+            }
+
+            Err(e) => {
+                log::error!("Failed to write `{}`: {}.", args.path, e);
+                promise.err(&write_error(&e))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn wily_mkdir(&self, args: WilyMkdirArgs, promise: Promise<(), WilyMkdirError>) {
+        log::info!("Creating directory `{}`.", args.path);
+        tokio::spawn(Self::wily_mkdir_impl(args, promise, self.shares.clone()));
+    }
+
+    async fn wily_mkdir_impl(
+        args: WilyMkdirArgs,
+        promise: Promise<(), WilyMkdirError>,
+        shares: Arc<RwLock<HashMap<String, Share>>>,
+    ) -> Result<()> {
+        let (parent, name) = match resolve::resolve_parent(&shares, &args.path).await {
+            Ok(Some(resolved)) => resolved,
+
+            Ok(None) => {
+                log::error!("Failed to create `{}`: directory exists.", args.path);
+                promise.err(&WilyMkdirError::AlreadyExists)?;
+                return Ok(());
+            }
+
+            Err(e) => {
+                log::error!("Failed to create `{}`: {}.", args.path, e);
+                promise.err(&WilyMkdirError::NotFound)?;
+                return Ok(());
+            }
+        };
+
+        match parent.access {
+            ShareAccess::ReadWrite => {}
+
+            ShareAccess::ReadOnly => {
+                log::error!("Failed to create `{}`: share is read-only.", args.path);
+                promise.err(&WilyMkdirError::ReadOnlyShare)?;
+                return Ok(());
+            }
+
+            ShareAccess::DropBox => {
+                log::error!("Failed to create `{}`: share is a drop box.", args.path);
+                promise.err(&WilyMkdirError::PermissionDenied)?;
+                return Ok(());
+            }
+        }
+
+        match async_fs::create_dir(parent.path.join(name)).await {
+            Ok(()) => promise.done()?,
+
+            Err(e) => {
+                log::error!("Failed to create `{}`: {}.", args.path, e);
+                promise.err(&mkdir_error(&e))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn wily_rename(&self, args: WilyRenameArgs, promise: Promise<(), WilyRenameError>) {
+        log::info!("Renaming `{}` to `{}`.", args.from, args.to);
+        tokio::spawn(Self::wily_rename_impl(args, promise, self.shares.clone()));
+    }
+
+    async fn wily_rename_impl(
+        args: WilyRenameArgs,
+        promise: Promise<(), WilyRenameError>,
+        shares: Arc<RwLock<HashMap<String, Share>>>,
+    ) -> Result<()> {
+        let from = resolve::resolve_parent(&shares, &args.from).await;
+        let to = resolve::resolve_parent(&shares, &args.to).await;
+
+        let ((from_parent, from_name), (to_parent, to_name)) = match (from, to) {
+            (Ok(Some(from)), Ok(Some(to))) => (from, to),
+
+            (Ok(None), _) | (_, Ok(None)) => {
+                log::error!("Failed to rename `{}`: cannot rename shares.", args.from);
+                promise.err(&WilyRenameError::PermissionDenied)?;
+                return Ok(());
+            }
+
+            (Err(e), _) | (_, Err(e)) => {
+                log::error!("Failed to rename `{}`: {}.", args.from, e);
+                promise.err(&WilyRenameError::NotFound)?;
+                return Ok(());
+            }
+        };
+
+        // Different shares may well have the same root directory.
+        if from_parent.share != to_parent.share {
+            log::error!("Failed to rename `{}`: different shares.", args.from);
+            promise.err(&WilyRenameError::CrossShare)?;
+            return Ok(());
+        }
+
+        // The share's access may have changed while resolving both paths.
+        for access in [from_parent.access, to_parent.access] {
+            match access {
+                ShareAccess::ReadWrite => {}
+
+                ShareAccess::ReadOnly => {
+                    log::error!("Failed to rename `{}`: share is read-only.", args.from);
+                    promise.err(&WilyRenameError::ReadOnlyShare)?;
+                    return Ok(());
+                }
+
+                ShareAccess::DropBox => {
+                    log::error!("Failed to rename `{}`: share is a drop box.", args.from);
+                    promise.err(&WilyRenameError::PermissionDenied)?;
+                    return Ok(());
+                }
+            }
+        }
+
+        let from = from_parent.path.join(from_name);
+        let to = to_parent.path.join(to_name);
+
+        let res = if args.overwrite.unwrap_or(false) {
+            async_fs::rename(&from, &to).await
+        } else {
+            match rename_no_replace(&from, &to) {
+                // Without support for RENAME_NOREPLACE, the target can only be checked beforehand.
+                Err(Errno::INVAL) => match async_fs::symlink_metadata(&to).await {
+                    Ok(_) => Err(ErrorKind::AlreadyExists.into()),
+                    Err(_) => async_fs::rename(&from, &to).await,
+                },
+
+                res => res.map_err(Into::into),
+            }
+        };
+
+        match res {
+            Ok(()) => promise.done()?,
+
+            Err(e) => {
+                log::error!("Failed to rename `{}`: {}.", args.from, e);
+                promise.err(&rename_error(&e))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn wily_remove(&self, args: WilyRemoveArgs, promise: Promise<(), WilyRemoveError>) {
+        log::info!("Removing `{}`.", args.path);
+        tokio::spawn(Self::wily_remove_impl(args, promise, self.shares.clone()));
+    }
+
+    async fn wily_remove_impl(
+        args: WilyRemoveArgs,
+        promise: Promise<(), WilyRemoveError>,
+        shares: Arc<RwLock<HashMap<String, Share>>>,
+    ) -> Result<()> {
+        let (parent, name) = match resolve::resolve_parent(&shares, &args.path).await {
+            Ok(Some(resolved)) => resolved,
+
+            Ok(None) => {
+                log::error!("Failed to remove `{}`: cannot remove shares.", args.path);
+                promise.err(&WilyRemoveError::PermissionDenied)?;
+                return Ok(());
+            }
+
+            Err(e) => {
+                log::error!("Failed to remove `{}`: {}.", args.path, e);
+                promise.err(&WilyRemoveError::NotFound)?;
+                return Ok(());
+            }
+        };
+
+        match parent.access {
+            ShareAccess::ReadWrite => {}
+
+            ShareAccess::ReadOnly => {
+                log::error!("Failed to remove `{}`: share is read-only.", args.path);
+                promise.err(&WilyRemoveError::ReadOnlyShare)?;
+                return Ok(());
+            }
+
+            ShareAccess::DropBox => {
+                log::error!("Failed to remove `{}`: share is a drop box.", args.path);
+                promise.err(&WilyRemoveError::PermissionDenied)?;
+                return Ok(());
+            }
+        }
+
+        // The final component is never followed, so that symbolic links themselves get removed.
+        let path = parent.path.join(name);
+
+        let res = match async_fs::symlink_metadata(&path).await {
+            Ok(metadata) if metadata.is_dir() => {
+                if args.recursive.unwrap_or(false) {
+                    async_fs::remove_dir_all(&path).await
+                } else if is_empty_dir(&path).await {
+                    async_fs::remove_dir(&path).await
+                } else {
+                    log::error!("Failed to remove `{}`: directory is not empty.", args.path);
+                    promise.err(&WilyRemoveError::NotEmpty)?;
+                    return Ok(());
                 }
+            }
+
+            Ok(_) => async_fs::remove_file(&path).await,
+            Err(e) => Err(e),
+        };
+
+        match res {
+            Ok(()) => promise.done()?,
 
-                resolved = Some(PathBuf::from(&share.path));
+            Err(e) => {
+                log::error!("Failed to remove `{}`: {}.", args.path, e);
+                promise.err(&remove_error(&e))?;
             }
         }
 
-        Ok(resolved)
+        Ok(())
+    }
+
+    fn wily_archive(&self, args: WilyArchiveArgs, promise: Promise<(), WilyArchiveError>) {
+        log::info!("Archiving directory `{}`.", args.path);
+
+        let client = ClientHandle::clone(&self.public_bus);
+        tokio::spawn(Self::wily_archive_impl(
+            args,
+            promise,
+            self.shares.clone(),
+            client,
+        ));
     }
+
+    async fn wily_archive_impl(
+        args: WilyArchiveArgs,
+        promise: Promise<(), WilyArchiveError>,
+        shares: Arc<RwLock<HashMap<String, Share>>>,
+        client: ClientHandle,
+    ) -> Result<()> {
+        let resolved = match resolve::resolve_path(&shares, &args.path).await {
+            Ok(Some(resolved)) => resolved,
+
+            Ok(None) => {
+                log::error!("Failed to archive `{}`: not a directory.", args.path);
+                promise.err(&WilyArchiveError::NotADirectory)?;
+                return Ok(());
+            }
+
+            Err(e) => {
+                log::error!("Failed to archive `{}`: {}.", args.path, e);
+                promise.err(&WilyArchiveError::FileNotFound)?;
+                return Ok(());
+            }
+        };
+
+        if resolved.access == ShareAccess::DropBox {
+            log::error!("Failed to archive `{}`: share is a drop box.", args.path);
+            promise.err(&WilyArchiveError::PermissionDenied)?;
+            return Ok(());
+        }
+
+        match async_fs::symlink_metadata(&resolved.path).await {
+            Ok(metadata) if metadata.is_dir() => {}
+
+            Ok(_) => {
+                log::error!("Failed to archive `{}`: not a directory.", args.path);
+                promise.err(&WilyArchiveError::NotADirectory)?;
+                return Ok(());
+            }
+
+            Err(e) => {
+                log::error!("Failed to archive `{}`: {}.", args.path, e);
+                promise.err(&WilyArchiveError::FileNotFound)?;
+                return Ok(());
+            }
+        }
+
+        // The top-level directory of the archive is named after the requested directory. The
+        // resolved path is used, because the requested one may e.g. end in `..`.
+        let name = if resolved.is_share_root() {
+            resolved.share.clone()
+        } else {
+            match resolved.path.file_name().and_then(|name| name.to_str()) {
+                Some(name) => name.to_owned(),
+                None => resolved.share.clone(),
+            }
+        };
+
+        let mut sender = args.sender.bind(client).claim().await?;
+        promise.done()?;
+
+        let compression = args.compression.unwrap_or_default();
+        let (chunks, mut receiver) = mpsc::channel(CHANNEL_CAPACITY as usize);
+
+        let writer = tokio::task::spawn_blocking(move || {
+            archive::write_archive(&resolved, &name, compression, chunks, CHUNK_SIZE)
+        });
+
+        while let Some(chunk) = receiver.recv().await {
+            sender
+                .send_item(&ArchiveChunk::Data(Bytes::new(&chunk)))
+                .await?;
+        }
+
+        // The archive is generated on the fly, so its outcome can only be reported in-band.
+        match writer.await? {
+            Ok(()) => {
+                sender.send_item(&ArchiveChunk::Done).await?;
+                log::info!("Finished archiving `{}`.", args.path);
+            }
+
+            Err(e) => {
+                sender.send_item(&ArchiveChunk::Failed).await?;
+                log::error!("Failed to archive `{}`: {}.", args.path, e);
+            }
+        }
+
+        sender.close().await?;
+        Ok(())
+    }
+
+    fn wily_hash(&self, args: WilyHashArgs, promise: Promise<WilyHashOk, WilyHashError>) {
+        log::info!("Hashing file `{}`.", args.path);
+
+        tokio::spawn(Self::wily_hash_impl(
+            args,
+            promise,
+            self.shares.clone(),
+            self.hash_cache.clone(),
+        ));
+    }
+
+    async fn wily_hash_impl(
+        args: WilyHashArgs,
+        promise: Promise<WilyHashOk, WilyHashError>,
+        shares: Arc<RwLock<HashMap<String, Share>>>,
+        hash_cache: Arc<Mutex<HashCache>>,
+    ) -> Result<()> {
+        let resolved = match resolve::resolve_path(&shares, &args.path).await {
+            Ok(Some(resolved)) => resolved,
+
+            Ok(None) => {
+                log::error!("Failed to hash `{}`: not a file.", args.path);
+                promise.err(&WilyHashError::NotAFile)?;
+                return Ok(());
+            }
+
+            Err(e) => {
+                log::error!("Failed to hash `{}`: {}.", args.path, e);
+                promise.err(&WilyHashError::FileNotFound)?;
+                return Ok(());
+            }
+        };
+
+        if resolved.access == ShareAccess::DropBox {
+            log::error!("Failed to hash `{}`: share is a drop box.", args.path);
+            promise.err(&WilyHashError::PermissionDenied)?;
+            return Ok(());
+        }
+
+        let metadata = match async_fs::symlink_metadata(&resolved.path).await {
+            Ok(metadata) if metadata.is_file() => metadata,
+
+            Ok(_) => {
+                log::error!("Failed to hash `{}`: not a file.", args.path);
+                promise.err(&WilyHashError::NotAFile)?;
+                return Ok(());
+            }
+
+            Err(e) => {
+                log::error!("Failed to hash `{}`: {}.", args.path, e);
+                promise.err(&WilyHashError::FileNotFound)?;
+                return Ok(());
+            }
+        };
+
+        let algorithm = args.algorithm.unwrap_or_default();
+        let size = metadata.len();
+        let modified_unix_ms = modified_unix_ms(&metadata);
+
+        let cached = hash_cache
+            .lock()
+            .get(&resolved.path, algorithm, size, modified_unix_ms);
+
+        let hash = match cached {
+            Some(hash) => hash,
+
+            None => {
+                let path = resolved.path.clone();
+                let res =
+                    tokio::task::spawn_blocking(move || utils::hash_file(&path, algorithm)).await?;
+
+                let hash = match res {
+                    Ok(hash) => hash,
+
+                    Err(e) => {
+                        log::error!("Failed to hash `{}`: {}.", args.path, e);
+                        promise.err(&WilyHashError::IoError)?;
+                        return Ok(());
+                    }
+                };
+
+                // Don't cache the hash if the file was modified while it was being hashed.
+                let unchanged =
+                    async_fs::symlink_metadata(&resolved.path)
+                        .await
+                        .is_ok_and(|metadata| {
+                            (metadata.len() == size)
+                                && (self::modified_unix_ms(&metadata) == modified_unix_ms)
+                        });
+
+                if unchanged {
+                    hash_cache.lock().insert(
+                        resolved.path,
+                        algorithm,
+                        size,
+                        modified_unix_ms,
+                        hash.clone(),
+                    );
+                }
+
+                hash
+            }
+        };
+
+        promise.ok(&WilyHashOk {
+            algorithm,
+            hash: Bytes::new(hash),
+            size,
+            modified_unix_ms,
+        })?;
+
+        Ok(())
+    }
+
+    fn wily_delta(&self, args: WilyDeltaArgs, promise: Promise<WilyDeltaOk, WilyDeltaError>) {
+        log::info!(
+            "Computing delta of `{}` ({} blocks of {} bytes).",
+            args.path,
+            args.signatures.len(),
+            args.block_size
+        );
+
+        let client = ClientHandle::clone(&self.public_bus);
+        tokio::spawn(Self::wily_delta_impl(
+            args,
+            promise,
+            self.shares.clone(),
+            client,
+        ));
+    }
+
+    async fn wily_delta_impl(
+        args: WilyDeltaArgs,
+        promise: Promise<WilyDeltaOk, WilyDeltaError>,
+        shares: Arc<RwLock<HashMap<String, Share>>>,
+        client: ClientHandle,
+    ) -> Result<()> {
+        let resolved = match resolve::resolve_path(&shares, &args.path).await {
+            Ok(Some(resolved)) => resolved,
+
+            Ok(None) => {
+                log::error!("Failed to compute delta of `{}`: not a file.", args.path);
+                promise.err(&WilyDeltaError::NotAFile)?;
+                return Ok(());
+            }
+
+            Err(e) => {
+                log::error!("Failed to compute delta of `{}`: {}.", args.path, e);
+                promise.err(&WilyDeltaError::FileNotFound)?;
+                return Ok(());
+            }
+        };
+
+        if resolved.access == ShareAccess::DropBox {
+            log::error!(
+                "Failed to compute delta of `{}`: share is a drop box.",
+                args.path
+            );
+            promise.err(&WilyDeltaError::PermissionDenied)?;
+            return Ok(());
+        }
+
+        let metadata = match async_fs::symlink_metadata(&resolved.path).await {
+            Ok(metadata) if metadata.is_file() => metadata,
+
+            Ok(_) => {
+                log::error!("Failed to compute delta of `{}`: not a file.", args.path);
+                promise.err(&WilyDeltaError::NotAFile)?;
+                return Ok(());
+            }
+
+            Err(e) => {
+                log::error!("Failed to compute delta of `{}`: {}.", args.path, e);
+                promise.err(&WilyDeltaError::FileNotFound)?;
+                return Ok(());
+            }
+        };
+
+        let size = metadata.len();
+
+        if !delta::signatures_acceptable(args.block_size, args.signatures.len(), size) {
+            log::error!(
+                "Failed to compute delta of `{}`: invalid signatures.",
+                args.path
+            );
+            promise.err(&WilyDeltaError::InvalidSignatures)?;
+            return Ok(());
+        }
+
+        let file = match File::open(&resolved.path).await {
+            Ok(file) => file.into_std().await,
+
+            Err(e) => {
+                log::error!("Failed to compute delta of `{}`: {}.", args.path, e);
+                promise.err(&WilyDeltaError::FileNotFound)?;
+                return Ok(());
+            }
+        };
+
+        let mut sender = args.sender.bind(client).claim().await?;
+
+        promise.ok(&WilyDeltaOk {
+            size,
+            modified_unix_ms: modified_unix_ms(&metadata),
+        })?;
+
+        let block_size = args.block_size;
+        let signatures = args.signatures;
+        let (ops, mut receiver) = mpsc::channel(CHANNEL_CAPACITY as usize);
+
+        // Never describe more than announced, even if the file grows in the meantime.
+        let computer = tokio::task::spawn_blocking(move || {
+            delta::compute(file.take(size), block_size, &signatures, |op| {
+                ops.blocking_send(op)
+                    .map_err(|_| io::Error::new(ErrorKind::BrokenPipe, "channel closed"))
+            })
+        });
+
+        while let Some(op) = receiver.recv().await {
+            sender.send_item(&op).await?;
+        }
+
+        match computer.await? {
+            Ok(()) => {
+                sender.close().await?;
+                log::info!("Finished computing delta of `{}`.", args.path);
+            }
+
+            // Dropping the sender without closing it signals the error to the client.
+            Err(e) => log::error!("Failed to compute delta of `{}`: {}.", args.path, e),
+        }
+
+        Ok(())
+    }
+
+    fn wily_watch(&self, args: WilyWatchArgs, promise: Promise<(), WilyWatchError>) {
+        log::info!("Watching path `{}`.", args.path);
+
+        let client = ClientHandle::clone(&self.public_bus);
+        tokio::spawn(Self::wily_watch_impl(
+            args,
+            promise,
+            self.shares.clone(),
+            client,
+        ));
+    }
+
+    async fn wily_watch_impl(
+        args: WilyWatchArgs,
+        promise: Promise<(), WilyWatchError>,
+        shares: Arc<RwLock<HashMap<String, Share>>>,
+        client: ClientHandle,
+    ) -> Result<()> {
+        let resolved = match resolve::resolve_path(&shares, &args.path).await {
+            Ok(Some(resolved)) => resolved,
+
+            Ok(None) => {
+                log::error!(
+                    "Failed to watch `{}`: the root can't be watched.",
+                    args.path
+                );
+                promise.err(&WilyWatchError::FileNotFound)?;
+                return Ok(());
+            }
+
+            Err(e) => {
+                log::error!("Failed to watch `{}`: {}.", args.path, e);
+                promise.err(&WilyWatchError::FileNotFound)?;
+                return Ok(());
+            }
+        };
+
+        if resolved.access == ShareAccess::DropBox {
+            log::error!("Failed to watch `{}`: share is a drop box.", args.path);
+            promise.err(&WilyWatchError::PermissionDenied)?;
+            return Ok(());
+        }
+
+        let share = resolved.share.clone();
+        let recursive = args.recursive.unwrap_or(false);
+        let mut watch = match Watch::new(resolved, &args.path, recursive).await {
+            Ok(watch) => watch,
+
+            Err(e) => {
+                log::error!("Failed to watch `{}`: {}.", args.path, e);
+                promise.err(&WilyWatchError::IoError)?;
+                return Ok(());
+            }
+        };
+
+        let mut sender = args.sender.bind(client).claim().await?;
+        promise.done()?;
+
+        let mut keepalive = tokio::time::interval(KEEPALIVE_INTERVAL);
+        keepalive.reset();
+        let mut share_check = tokio::time::interval(SHARE_CHECK_INTERVAL);
+
+        // The watch ends when the path is removed, when the share is unshared or disabled, or when
+        // sending fails, which is usually because the client went away. Keep-alive events make
+        // sure the latter is noticed even when nothing changes.
+        let res = loop {
+            let events = tokio::select! {
+                events = watch.next() => match events {
+                    Ok(Some(events)) => events,
+                    Ok(None) => break Ok("path was removed"),
+                    Err(e) => break Err(e.into()),
+                },
+
+                _ = keepalive.tick() => {
+                    let mut events: Vec<_> = watch.flush().into_iter().collect();
+                    events.push(WatchEvent::KeepAlive);
+                    events
+                }
+
+                _ = share_check.tick() => {
+                    if !resolve::is_share_active(&shares, &share) {
+                        break send_events(&mut sender, &[WatchEvent::Unavailable])
+                            .await
+                            .map(|()| "share is no longer available");
+                    }
+
+                    continue;
+                }
+            };
+
+            if let Err(e) = send_events(&mut sender, &events).await {
+                break Err(e);
+            }
+        };
+
+        match res {
+            Ok(reason) => {
+                sender.close().await?;
+                log::info!("Stopped watching `{}`: {reason}.", args.path);
+            }
+
+            Err(e) => log::info!("Stopped watching `{}`: {}.", args.path, e),
+        }
+
+        Ok(())
+    }
+
+    fn wily_search(&self, args: WilySearchArgs, promise: Promise<(), WilySearchError>) {
+        log::info!("Searching `{}` for `{}`.", args.path, args.pattern);
+
+        let client = ClientHandle::clone(&self.public_bus);
+        tokio::spawn(Self::wily_search_impl(
+            args,
+            promise,
+            self.shares.clone(),
+            client,
+        ));
+    }
+
+    async fn wily_search_impl(
+        args: WilySearchArgs,
+        promise: Promise<(), WilySearchError>,
+        shares: Arc<RwLock<HashMap<String, Share>>>,
+        client: ClientHandle,
+    ) -> Result<()> {
+        let search = match Search::new(&args) {
+            Ok(search) => search,
+
+            Err(e) => {
+                log::error!("Failed to search `{}`: {}.", args.path, e);
+                promise.err(&WilySearchError::InvalidPattern(e))?;
+                return Ok(());
+            }
+        };
+
+        let resolved = match resolve::resolve_path(&shares, &args.path).await {
+            Ok(Some(resolved)) => resolved,
+
+            Ok(None) => {
+                log::error!(
+                    "Failed to search `{}`: the root can't be searched.",
+                    args.path
+                );
+                promise.err(&WilySearchError::NotADirectory)?;
+                return Ok(());
+            }
+
+            Err(e) => {
+                log::error!("Failed to search `{}`: {}.", args.path, e);
+                promise.err(&WilySearchError::FileNotFound)?;
+                return Ok(());
+            }
+        };
+
+        if resolved.access == ShareAccess::DropBox {
+            log::error!("Failed to search `{}`: share is a drop box.", args.path);
+            promise.err(&WilySearchError::PermissionDenied)?;
+            return Ok(());
+        }
+
+        match async_fs::symlink_metadata(&resolved.path).await {
+            Ok(metadata) if metadata.is_dir() => {}
+
+            Ok(_) => {
+                log::error!("Failed to search `{}`: not a directory.", args.path);
+                promise.err(&WilySearchError::NotADirectory)?;
+                return Ok(());
+            }
+
+            Err(e) => {
+                log::error!("Failed to search `{}`: {}.", args.path, e);
+                promise.err(&WilySearchError::FileNotFound)?;
+                return Ok(());
+            }
+        }
+
+        let mut sender = args.sender.bind(client).claim().await?;
+        promise.done()?;
+
+        let found = search.run(&resolved, &args.path, &mut sender).await?;
+        sender.close().await?;
+        log::info!("Finished searching `{}` ({found} matches).", args.path);
+
+        Ok(())
+    }
+
+    fn wily_server_info(&self, promise: Promise<ServerInfo, Infallible>) {
+        log::info!("Reporting server info.");
+
+        let info = ServerInfo {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            protocol: WILY_PROTOCOL,
+            features: features::ALL.iter().map(|&f| f.to_owned()).collect(),
+            max_chunk_size: CHUNK_SIZE as u32,
+            max_list_limit: MAX_LIST_LIMIT,
+            max_search_limit: search::MAX_LIMIT,
+            max_block_size: delta::MAX_BLOCK_SIZE,
+            max_signatures: delta::MAX_SIGNATURES as u32,
+        };
+
+        if let Err(e) = promise.ok(&info) {
+            log::error!("Failed to report server info: {e}.");
+        }
+    }
+
+    fn wily_identity(&self, promise: Promise<Identity, Infallible>) {
+        log::info!("Reporting identity.");
+
+        if let Err(e) = promise.ok(&self.identity) {
+            log::error!("Failed to report identity: {e}.");
+        }
+    }
+}
+
+async fn list_root(
+    shares: &RwLock<HashMap<String, Share>>,
+    cursor: Option<&str>,
+    limit: usize,
+) -> WilyListOk {
+    let mut roots: HashMap<_, _> = shares
+        .read()
+        .values()
+        .filter(|share| !share.disabled.any())
+        .map(|share| (share.name.clone(), share.path.clone()))
+        .collect();
+
+    let mut page = Page::new(cursor, limit);
+    for name in roots.keys() {
+        page.push(name.clone());
+    }
+
+    let (names, cursor) = page.finish();
+    let mut entries = Vec::with_capacity(names.len());
+
+    for name in names {
+        let path = roots.remove(&name).unwrap();
+
+        match async_fs::metadata(&path).await {
+            Ok(metadata) => entries.push(DirEntry {
+                metadata: file_metadata(Path::new(&path), &metadata).await,
+                name,
+            }),
+
+            Err(e) => log::warn!("Failed to query share `{name}` (`{path}`): {e}."),
+        }
+    }
+
+    WilyListOk { entries, cursor }
+}
+
+async fn list_directory(
+    resolved: &ResolvedPath,
+    cursor: Option<&str>,
+    limit: usize,
+) -> Result<WilyListOk> {
+    let mut read_dir = async_fs::read_dir(&resolved.path).await?;
+    let mut page = Page::new(cursor, limit);
+
+    while let Some(entry) = read_dir.next_entry().await? {
+        match entry.file_name().into_string() {
+            Ok(name) => page.push(name),
+            Err(name) => log::warn!("Skipping non UTF-8 file name {name:?}."),
+        }
+    }
+
+    let (names, cursor) = page.finish();
+    let mut entries = Vec::with_capacity(names.len());
+
+    for name in names {
+        let path = resolved.path.join(&name);
+
+        match resolved.entry_metadata(&path).await {
+            Ok(Some(metadata)) => entries.push(DirEntry {
+                name,
+                metadata: file_metadata(&path, &metadata).await,
+            }),
+
+            Ok(None) => {}
+            Err(e) => log::warn!("Failed to query `{name}`: {e}."),
+        }
+    }
+
+    Ok(WilyListOk { entries, cursor })
+}
+
+async fn receive_file(
+    mut file: File,
+    receiver: UnboundReceiver<Bytes>,
+    client: ClientHandle,
+) -> Result<u64> {
+    let mut receiver = receiver.bind(client).claim(CHANNEL_CAPACITY).await?;
+    let mut size = 0;
+
+    while let Some(chunk) = receiver.next_item().await? {
+        file.write_all(&chunk).await?;
+        size += chunk.len() as u64;
+    }
+
+    file.sync_all().await?;
+    Ok(size)
+}
+
+/// Keeps sending data that is appended to a file.
+///
+/// Truncated files are sent again from the start. If the path is replaced by a different file,
+/// e.g. when logs are rotated, then the new file is followed after the old one has been sent
+/// completely. `Ok(())` is returned when `share` is unshared, disabled or expires.
+async fn follow_file(
+    mut file: File,
+    shares: &RwLock<HashMap<String, Share>>,
+    share: &str,
+    path: &str,
+    mut pos: u64,
+    sender: &mut Sender<Bytes>,
+) -> Result<()> {
+    let mut buf = vec![0; CHUNK_SIZE];
+    let mut idle = Duration::ZERO;
+
+    loop {
+        if !resolve::is_share_active(shares, share) {
+            return Ok(());
+        }
+
+        let len = file.read(&mut buf).await?;
+
+        if len > 0 {
+            sender.send_item(&Bytes::new(&buf[..len])).await?;
+            pos += len as u64;
+            idle = Duration::ZERO;
+            continue;
+        }
+
+        tokio::time::sleep(FOLLOW_INTERVAL).await;
+        idle += FOLLOW_INTERVAL;
+
+        // Empty chunks make sure a client that went away is noticed.
+        if idle >= KEEPALIVE_INTERVAL {
+            sender.send_item(&Bytes::new(Vec::new())).await?;
+            idle = Duration::ZERO;
+        }
+
+        let current = file.metadata().await?;
+
+        if current.len() < pos {
+            log::info!("`{path}` was truncated.");
+            file.seek(SeekFrom::Start(0)).await?;
+            pos = 0;
+            continue;
+        }
+
+        // The path is resolved again, so that a replacement is subject to the share's symlink
+        // policy. It may be missing for a moment during rotation. Just try again later then.
+        let Ok(Some(resolved)) = resolve::resolve_path(shares, path).await else {
+            continue;
+        };
+
+        let Ok(metadata) = async_fs::symlink_metadata(&resolved.path).await else {
+            continue;
+        };
+
+        if !metadata.is_file() || is_same_file(&metadata, &current) {
+            continue;
+        }
+
+        let Ok(new) = File::open(&resolved.path).await else {
+            continue;
+        };
+
+        // The path may have been replaced again in the meantime, e.g. by a symbolic link.
+        if new
+            .metadata()
+            .await
+            .is_ok_and(|opened| is_same_file(&opened, &metadata))
+        {
+            log::info!("`{path}` was replaced.");
+            file = new;
+            pos = 0;
+        }
+    }
+}
+
+fn is_same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    (a.ino() == b.ino()) && (a.dev() == b.dev())
+}
+
+async fn send_events(sender: &mut Sender<WatchEvent>, events: &[WatchEvent]) -> Result<()> {
+    for event in events {
+        sender.send_item(event).await?;
+    }
+
+    Ok(())
+}
+
+/// Renames `from` to `to`, unless `to` exists already.
+///
+/// File systems without support for `RENAME_NOREPLACE` fail with `Errno::INVAL`.
+fn rename_no_replace(from: &Path, to: &Path) -> rustix::io::Result<()> {
+    rustix::fs::renameat_with(CWD, from, CWD, to, RenameFlags::NOREPLACE)
+}
+
+async fn remove_tmp_file(path: &Path) {
+    match async_fs::remove_file(path).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => log::warn!("Failed to remove `{}`: {e}.", path.display()),
+    }
+}
+
+/// Moves a finished upload from `tmp_path` to `path`, unless that exists already.
+async fn commit_upload(tmp_path: &Path, path: &Path) -> io::Result<()> {
+    match rename_no_replace(tmp_path, path) {
+        // Hard links never replace existing files either.
+        Err(Errno::INVAL) => async_fs::hard_link(tmp_path, path).await,
+        res => res.map_err(Into::into),
+    }
+}
+
+/// Moves a finished upload into `dir` under `name` or, if that is taken, under `name (N)`.
+///
+/// Returns the name that was used.
+async fn commit_unique(tmp_path: &Path, dir: &Path, name: &str) -> io::Result<String> {
+    const MAX_ATTEMPTS: u32 = 1000;
+
+    let (stem, ext) = match name.rfind('.') {
+        Some(i) if i > 0 => name.split_at(i),
+        _ => (name, ""),
+    };
+
+    for i in 0..MAX_ATTEMPTS {
+        let candidate = if i == 0 {
+            name.to_owned()
+        } else {
+            format!("{stem} ({i}){ext}")
+        };
+
+        match commit_upload(tmp_path, &dir.join(&candidate)).await {
+            Ok(()) => return Ok(candidate),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(io::Error::new(
+        ErrorKind::AlreadyExists,
+        "no unique file name found",
+    ))
+}
+
+fn write_error(e: &io::Error) -> WilyWriteError {
+    match e.kind() {
+        ErrorKind::NotFound => WilyWriteError::FileNotFound,
+        ErrorKind::AlreadyExists => WilyWriteError::AlreadyExists,
+        ErrorKind::PermissionDenied => WilyWriteError::PermissionDenied,
+        _ => WilyWriteError::IoError,
+    }
+}
+
+fn mkdir_error(e: &io::Error) -> WilyMkdirError {
+    match e.kind() {
+        ErrorKind::NotFound => WilyMkdirError::NotFound,
+        ErrorKind::AlreadyExists => WilyMkdirError::AlreadyExists,
+        ErrorKind::PermissionDenied => WilyMkdirError::PermissionDenied,
+        _ => WilyMkdirError::IoError,
+    }
+}
+
+fn rename_error(e: &io::Error) -> WilyRenameError {
+    match e.kind() {
+        ErrorKind::NotFound => WilyRenameError::NotFound,
+        ErrorKind::AlreadyExists => WilyRenameError::AlreadyExists,
+        ErrorKind::PermissionDenied => WilyRenameError::PermissionDenied,
+        _ => WilyRenameError::IoError,
+    }
+}
+
+fn remove_error(e: &io::Error) -> WilyRemoveError {
+    match e.kind() {
+        ErrorKind::NotFound => WilyRemoveError::NotFound,
+        ErrorKind::PermissionDenied => WilyRemoveError::PermissionDenied,
+        _ => WilyRemoveError::IoError,
+    }
+}
+
+async fn is_empty_dir(path: &Path) -> bool {
+    match async_fs::read_dir(path).await {
+        Ok(mut read_dir) => matches!(read_dir.next_entry().await, Ok(None)),
+        Err(_) => false,
+    }
+}
+
+/// Collects the first `limit` names in sort order that come after a cursor.
+///
+/// Only the current page is kept and sorted, so that large directories don't have to be held in
+/// memory and sorted for every page.
+struct Page<'a> {
+    cursor: Option<&'a str>,
+    limit: usize,
+
+    /// Max-heap of the smallest `limit + 1` names pushed so far.
+    names: BinaryHeap<String>,
+}
+
+impl<'a> Page<'a> {
+    fn new(cursor: Option<&'a str>, limit: usize) -> Self {
+        Self {
+            cursor,
+            limit,
+            names: BinaryHeap::with_capacity(limit + 1),
+        }
+    }
+
+    fn push(&mut self, name: String) {
+        if self.cursor.is_some_and(|cursor| name.as_str() <= cursor) {
+            return;
+        }
+
+        if self.names.len() <= self.limit {
+            self.names.push(name);
+        } else if let Some(mut largest) = self.names.peek_mut() {
+            if name < *largest {
+                *largest = name;
+            }
+        }
+    }
+
+    /// Returns the sorted page and the cursor of the next page, if there are more names left.
+    fn finish(self) -> (Vec<String>, Option<String>) {
+        let mut names = self.names.into_sorted_vec();
+
+        if names.len() > self.limit {
+            names.truncate(self.limit);
+            let cursor = names.last().cloned();
+            (names, cursor)
+        } else {
+            (names, None)
+        }
+    }
+}
+
+pub(super) async fn file_metadata(path: &Path, metadata: &fs::Metadata) -> Metadata {
+    // Readability is checked with access(2) instead of opening the file, which would block forever
+    // on e.g. FIFOs.
+    let readable = || rustix::fs::access(path, Access::READ_OK).is_ok();
+
+    let (file_type, symlink_target, readable) = if metadata.is_dir() {
+        (FileType::Directory, None, Some(readable()))
+    } else if metadata.is_symlink() {
+        let target = async_fs::read_link(path)
+            .await
+            .ok()
+            .and_then(|target| target.into_os_string().into_string().ok());
+
+        (FileType::SymLink, target, None)
+    } else {
+        (FileType::File, None, Some(readable()))
+    };
+
+    Metadata {
+        file_type,
+        size: Some(metadata.len()),
+        modified_unix_ms: modified_unix_ms(metadata),
+        mode: Some(metadata.permissions().mode() & 0o7777),
+        symlink_target,
+        readable,
+    }
+}
+
+fn modified_unix_ms(metadata: &fs::Metadata) -> Option<i64> {
+    metadata
+        .modified()
+        .ok()
+        .map(|modified| DateTime::<Utc>::from(modified).timestamp_millis())
 }
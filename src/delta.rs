@@ -0,0 +1,531 @@
+//! rsync-style delta transfer.
+//!
+//! The receiver splits its local copy of a file into blocks and sends a signature of each block,
+//! consisting of a cheap rolling checksum and a strong hash. The sender then slides a window over
+//! its version of the file and describes it as a sequence of copies of the receiver's blocks and
+//! literal data.
+
+use crate::schemas::{BlockSignature, DeltaCopy, DeltaOp};
+use aldrin::core::Bytes;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read};
+use std::mem;
+use std::path::Path;
+
+pub const MIN_BLOCK_SIZE: u32 = 512;
+pub const MAX_BLOCK_SIZE: u32 = 16 * 1024 * 1024;
+pub const MAX_SIGNATURES: usize = 1024 * 1024;
+
+const TARGET_SIGNATURES: u64 = 64 * 1024;
+const MAX_BASE_RATIO: u64 = 2;
+const MAX_CANDIDATES: usize = 16;
+const MAX_FALSE_MATCHES: u32 = 64;
+const STRONG_HASH_LEN: usize = 16;
+const MAX_LITERAL_LEN: usize = 64 * 1024;
+const READ_AHEAD: usize = 256 * 1024;
+
+/// Chooses a block size for a file of `size` bytes.
+///
+/// This is roughly the square root of the size, but grows further for very large files, so that
+/// the number of signatures stays manageable.
+pub fn block_size(size: u64) -> u32 {
+    let sqrt = (size as f64).sqrt() as u64;
+    let min = (size + TARGET_SIGNATURES - 1) / TARGET_SIGNATURES;
+
+    sqrt.max(min)
+        .next_power_of_two()
+        .clamp(MIN_BLOCK_SIZE as u64, MAX_BLOCK_SIZE as u64) as u32
+}
+
+/// Checks whether `count` signatures of blocks of `block_size` bytes can be used to compute the
+/// delta of a file of `size` bytes.
+///
+/// The signatures must not describe much more data than the file itself, which bounds the time
+/// spent on computing the delta.
+pub fn signatures_acceptable(block_size: u32, count: usize, size: u64) -> bool {
+    (MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&block_size)
+        && (count <= MAX_SIGNATURES)
+        && (count as u64 * block_size as u64 <= size.saturating_mul(MAX_BASE_RATIO))
+}
+
+/// Computes the signatures of all full blocks of a file.
+///
+/// A trailing partial block has no signature and will always be transferred literally. This
+/// blocks and must be called via `tokio::task::spawn_blocking` from async code.
+pub fn signatures(path: &Path, block_size: u32) -> io::Result<Vec<BlockSignature>> {
+    read_signatures(File::open(path)?, block_size)
+}
+
+fn read_signatures<R: Read>(mut reader: R, block_size: u32) -> io::Result<Vec<BlockSignature>> {
+    let mut block = vec![0; block_size as usize];
+    let mut signatures = Vec::new();
+
+    loop {
+        match reader.read_exact(&mut block) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+
+        signatures.push(BlockSignature {
+            weak: Rolling::new(&block).value(),
+            strong: Bytes::new(strong_hash(&block)),
+        });
+    }
+
+    Ok(signatures)
+}
+
+/// Computes the delta of `reader` relative to the blocks described by `signatures`.
+///
+/// This blocks and must be called via `tokio::task::spawn_blocking` from async code.
+pub fn compute<R, F>(
+    mut reader: R,
+    block_size: u32,
+    signatures: &[BlockSignature],
+    emit: F,
+) -> io::Result<()>
+where
+    R: Read,
+    F: FnMut(DeltaOp) -> io::Result<()>,
+{
+    let block_size = block_size as usize;
+    let mut blocks = Blocks::new(signatures);
+    let mut ops = Ops::new(emit);
+    let mut buf = Vec::new();
+    let mut pos = 0;
+    let mut eof = false;
+    let mut rolling: Option<Rolling> = None;
+
+    loop {
+        // Keep the window and the next byte in the buffer, so that the checksum can roll.
+        if !eof && (buf.len() < pos + block_size + 1) {
+            buf.drain(..pos);
+            pos = 0;
+            eof = fill(&mut reader, &mut buf, block_size + 1 + READ_AHEAD)?;
+        }
+
+        if buf.len() - pos < block_size {
+            break;
+        }
+
+        let window = &buf[pos..pos + block_size];
+        let checksum = rolling.get_or_insert_with(|| Rolling::new(window));
+
+        if let Some(block) = blocks.find(checksum.value(), window) {
+            ops.copy(block)?;
+            pos += block_size;
+            rolling = None;
+            continue;
+        }
+
+        ops.literal(&buf[pos..pos + 1])?;
+
+        match buf.get(pos + block_size) {
+            Some(&next) => checksum.roll(buf[pos], next),
+            None => rolling = None,
+        }
+
+        pos += 1;
+    }
+
+    ops.literal(&buf[pos..])?;
+    ops.finish()
+}
+
+fn strong_hash(block: &[u8]) -> Vec<u8> {
+    blake3::hash(block).as_bytes()[..STRONG_HASH_LEN].to_vec()
+}
+
+/// Looks up blocks by their signatures.
+struct Blocks<'a> {
+    signatures: &'a [BlockSignature],
+    candidates: HashMap<u32, Candidates>,
+}
+
+struct Candidates {
+    blocks: Vec<u64>,
+    false_matches: u32,
+}
+
+impl<'a> Blocks<'a> {
+    fn new(signatures: &'a [BlockSignature]) -> Self {
+        let mut candidates: HashMap<u32, Candidates> = HashMap::new();
+
+        for (i, signature) in signatures.iter().enumerate() {
+            let candidates = candidates
+                .entry(signature.weak)
+                .or_insert_with(|| Candidates {
+                    blocks: Vec::new(),
+                    false_matches: 0,
+                });
+
+            // Like rsync, only a limited number of blocks is considered per weak checksum.
+            if candidates.blocks.len() < MAX_CANDIDATES {
+                candidates.blocks.push(i as u64);
+            }
+        }
+
+        Self {
+            signatures,
+            candidates,
+        }
+    }
+
+    fn find(&mut self, weak: u32, window: &[u8]) -> Option<u64> {
+        let candidates = self.candidates.get_mut(&weak)?;
+        let strong = strong_hash(window);
+
+        let block = candidates
+            .blocks
+            .iter()
+            .copied()
+            .find(|&block| self.signatures[block as usize].strong[..] == strong[..]);
+
+        // Weak checksums, which keep matching without a matching block, are dropped. Otherwise
+        // forged signatures could cause a strong hash to be computed at every position.
+        if block.is_none() {
+            candidates.false_matches += 1;
+
+            if candidates.false_matches >= MAX_FALSE_MATCHES {
+                self.candidates.remove(&weak);
+            }
+        }
+
+        block
+    }
+}
+
+/// Reads until `buf` holds at least `len` bytes. Returns `true` on EOF.
+fn fill<R: Read>(reader: &mut R, buf: &mut Vec<u8>, len: usize) -> io::Result<bool> {
+    let mut chunk = vec![0; READ_AHEAD];
+
+    while buf.len() < len {
+        let n = match reader.read(&mut chunk) {
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        if n == 0 {
+            return Ok(true);
+        }
+
+        buf.extend_from_slice(&chunk[..n]);
+    }
+
+    Ok(false)
+}
+
+/// The rolling checksum of rsync.
+struct Rolling {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl Rolling {
+    fn new(block: &[u8]) -> Self {
+        let mut a: u32 = 0;
+        let mut b: u32 = 0;
+
+        for (i, &byte) in block.iter().enumerate() {
+            a = a.wrapping_add(byte as u32);
+            b = b.wrapping_add(((block.len() - i) as u32).wrapping_mul(byte as u32));
+        }
+
+        Self {
+            a,
+            b,
+            len: block.len() as u32,
+        }
+    }
+
+    fn roll(&mut self, out: u8, next: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(next as u32);
+        self.b = self
+            .b
+            .wrapping_sub(self.len.wrapping_mul(out as u32))
+            .wrapping_add(self.a);
+    }
+
+    fn value(&self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+}
+
+/// Merges adjacent copies and buffers literal data before emitting ops.
+struct Ops<F> {
+    emit: F,
+    copy: Option<DeltaCopy>,
+    literal: Vec<u8>,
+}
+
+impl<F> Ops<F>
+where
+    F: FnMut(DeltaOp) -> io::Result<()>,
+{
+    fn new(emit: F) -> Self {
+        Self {
+            emit,
+            copy: None,
+            literal: Vec::new(),
+        }
+    }
+
+    fn copy(&mut self, block: u64) -> io::Result<()> {
+        self.flush_literal()?;
+
+        if let Some(ref mut copy) = self.copy {
+            if (copy.block + copy.count as u64 == block) && (copy.count < u32::MAX) {
+                copy.count += 1;
+                return Ok(());
+            }
+        }
+
+        self.flush_copy()?;
+        self.copy = Some(DeltaCopy { block, count: 1 });
+        Ok(())
+    }
+
+    fn literal(&mut self, mut data: &[u8]) -> io::Result<()> {
+        self.flush_copy()?;
+
+        while !data.is_empty() {
+            let len = data.len().min(MAX_LITERAL_LEN - self.literal.len());
+            self.literal.extend_from_slice(&data[..len]);
+            data = &data[len..];
+
+            if self.literal.len() >= MAX_LITERAL_LEN {
+                self.flush_literal()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.flush_copy()?;
+        self.flush_literal()
+    }
+
+    fn flush_copy(&mut self) -> io::Result<()> {
+        match self.copy.take() {
+            Some(copy) => (self.emit)(DeltaOp::Copy(copy)),
+            None => Ok(()),
+        }
+    }
+
+    fn flush_literal(&mut self) -> io::Result<()> {
+        if self.literal.is_empty() {
+            return Ok(());
+        }
+
+        let literal = mem::take(&mut self.literal);
+        (self.emit)(DeltaOp::Literal(Bytes::new(literal)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK_SIZE: u32 = 16;
+
+    /// Generates reproducible data without repeating blocks.
+    fn data(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    fn delta(base: &[u8], new: &[u8]) -> Vec<DeltaOp> {
+        let signatures = read_signatures(base, BLOCK_SIZE).unwrap();
+        let mut ops = Vec::new();
+
+        compute(new, BLOCK_SIZE, &signatures, |op| {
+            ops.push(op);
+            Ok(())
+        })
+        .unwrap();
+
+        ops
+    }
+
+    fn apply(base: &[u8], ops: &[DeltaOp]) -> Vec<u8> {
+        let block_size = BLOCK_SIZE as usize;
+        let mut res = Vec::new();
+
+        for op in ops {
+            match op {
+                DeltaOp::Copy(copy) => {
+                    let start = copy.block as usize * block_size;
+                    let end = start + copy.count as usize * block_size;
+                    res.extend_from_slice(&base[start..end]);
+                }
+
+                DeltaOp::Literal(data) => res.extend_from_slice(data),
+            }
+        }
+
+        res
+    }
+
+    fn literal_len(ops: &[DeltaOp]) -> usize {
+        ops.iter()
+            .map(|op| match op {
+                DeltaOp::Copy(_) => 0,
+                DeltaOp::Literal(data) => data.len(),
+            })
+            .sum()
+    }
+
+    #[test]
+    fn unchanged() {
+        let base = data(10 * BLOCK_SIZE as usize, 1);
+        let ops = delta(&base, &base);
+
+        assert_eq!(ops.len(), 1);
+        assert!(matches!(
+            ops[0],
+            DeltaOp::Copy(DeltaCopy {
+                block: 0,
+                count: 10
+            })
+        ));
+        assert_eq!(apply(&base, &ops), base);
+    }
+
+    #[test]
+    fn partial_block() {
+        let base = data(10 * BLOCK_SIZE as usize + 5, 2);
+        let ops = delta(&base, &base);
+
+        assert_eq!(literal_len(&ops), 5);
+        assert_eq!(apply(&base, &ops), base);
+    }
+
+    #[test]
+    fn insert() {
+        let base = data(10 * BLOCK_SIZE as usize, 3);
+
+        let mut new = base.clone();
+        let pos = 3 * BLOCK_SIZE as usize + 7;
+        new.splice(pos..pos, data(5, 4));
+
+        let ops = delta(&base, &new);
+
+        assert_eq!(apply(&base, &ops), new);
+        // Only the block with the insertion is transferred.
+        assert_eq!(literal_len(&ops), 5 + BLOCK_SIZE as usize);
+    }
+
+    #[test]
+    fn delete_across_block_boundary() {
+        let base = data(10 * BLOCK_SIZE as usize, 5);
+
+        let mut new = base.clone();
+        let pos = 4 * BLOCK_SIZE as usize - 3;
+        new.drain(pos..pos + 6);
+
+        let ops = delta(&base, &new);
+
+        assert_eq!(apply(&base, &ops), new);
+        // Only the two blocks with the deletion are transferred.
+        assert_eq!(literal_len(&ops), 2 * BLOCK_SIZE as usize - 6);
+    }
+
+    #[test]
+    fn moved_blocks() {
+        let base = data(10 * BLOCK_SIZE as usize, 6);
+        let (head, tail) = base.split_at(4 * BLOCK_SIZE as usize);
+        let new = [tail, head].concat();
+
+        let ops = delta(&base, &new);
+
+        assert_eq!(apply(&base, &ops), new);
+        assert_eq!(literal_len(&ops), 0);
+    }
+
+    #[test]
+    fn empty_base() {
+        let new = data(3 * BLOCK_SIZE as usize, 7);
+        let ops = delta(&[], &new);
+
+        assert_eq!(literal_len(&ops), new.len());
+        assert_eq!(apply(&[], &ops), new);
+    }
+
+    #[test]
+    fn empty_new() {
+        let base = data(3 * BLOCK_SIZE as usize, 8);
+        let ops = delta(&base, &[]);
+
+        assert!(ops.is_empty());
+    }
+
+    #[test]
+    fn forged_signatures() {
+        let new = vec![0; 10 * BLOCK_SIZE as usize];
+        let weak = Rolling::new(&new[..BLOCK_SIZE as usize]).value();
+
+        let signatures: Vec<_> = (0..2 * MAX_CANDIDATES as u32)
+            .map(|i| BlockSignature {
+                weak,
+                strong: Bytes::new(strong_hash(&i.to_le_bytes())),
+            })
+            .collect();
+
+        let mut blocks = Blocks::new(&signatures);
+        assert_eq!(blocks.candidates[&weak].blocks.len(), MAX_CANDIDATES);
+
+        for _ in 0..MAX_FALSE_MATCHES {
+            assert_eq!(blocks.find(weak, &new[..BLOCK_SIZE as usize]), None);
+        }
+
+        assert!(blocks.candidates.is_empty());
+
+        let mut ops = Vec::new();
+        compute(&new[..], BLOCK_SIZE, &signatures, |op| {
+            ops.push(op);
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(apply(&[], &ops), new);
+    }
+
+    #[test]
+    fn signature_limits() {
+        let size = 2 * MIN_BLOCK_SIZE as u64;
+
+        assert!(signatures_acceptable(MIN_BLOCK_SIZE, 4, size));
+        assert!(!signatures_acceptable(MIN_BLOCK_SIZE, 5, size));
+        assert!(!signatures_acceptable(MIN_BLOCK_SIZE - 1, 1, u64::MAX));
+        assert!(!signatures_acceptable(MAX_BLOCK_SIZE + 1, 1, u64::MAX));
+        assert!(!signatures_acceptable(
+            MIN_BLOCK_SIZE,
+            MAX_SIGNATURES + 1,
+            u64::MAX
+        ));
+    }
+
+    #[test]
+    fn rolling_checksum() {
+        let data = data(4 * BLOCK_SIZE as usize, 9);
+        let block_size = BLOCK_SIZE as usize;
+        let mut rolling = Rolling::new(&data[..block_size]);
+
+        for pos in 1..data.len() - block_size {
+            rolling.roll(data[pos - 1], data[pos + block_size - 1]);
+            let fresh = Rolling::new(&data[pos..pos + block_size]);
+            assert_eq!(rolling.value(), fresh.value());
+        }
+    }
+}
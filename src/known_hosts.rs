@@ -0,0 +1,147 @@
+use crate::utils;
+use anyhow::{anyhow, Context, Result};
+use rustix::fs::FlockOperation;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+const KNOWN_HOSTS_FILE: &str = "known_hosts";
+const LOCK_FILE: &str = "known_hosts.lock";
+
+/// Certificate fingerprints of daemons, pinned on first use.
+///
+/// The file contains one `host:port fingerprint` pair per line. Empty lines and lines starting
+/// with `#` are ignored.
+#[derive(Debug)]
+pub struct KnownHosts {
+    path: PathBuf,
+    hosts: BTreeMap<String, String>,
+
+    /// Held while the known hosts are being modified. The lock is released when this is closed.
+    lock: Option<File>,
+}
+
+impl KnownHosts {
+    /// Loads the known hosts for reading only.
+    pub fn load() -> Result<Self> {
+        Self::load_impl(None)
+    }
+
+    /// Locks and loads the known hosts for modification.
+    ///
+    /// The lock is held until the returned value is dropped, so that concurrent clients can't
+    /// lose each other's changes.
+    pub fn lock() -> Result<Self> {
+        let dir = utils::data_dir()?;
+        fs::create_dir_all(&dir)
+            .with_context(|| anyhow!("failed to create directory `{}`", dir.display()))?;
+
+        let lock_path = dir.join(LOCK_FILE);
+        let lock = lock_file(&lock_path)
+            .with_context(|| anyhow!("failed to lock `{}`", lock_path.display()))?;
+
+        Self::load_impl(Some(lock))
+    }
+
+    fn load_impl(lock: Option<File>) -> Result<Self> {
+        let mut path = utils::data_dir()?;
+        path.push(KNOWN_HOSTS_FILE);
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(e).with_context(|| anyhow!("failed to read `{}`", path.display()));
+            }
+        };
+
+        let mut hosts = BTreeMap::new();
+
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (host, fingerprint) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| anyhow!("invalid line {} in `{}`", i + 1, path.display()))?;
+
+            hosts.insert(host.to_owned(), fingerprint.trim().to_owned());
+        }
+
+        Ok(Self { path, hosts, lock })
+    }
+
+    pub fn get(&self, host: &str) -> Option<&str> {
+        self.hosts.get(host).map(String::as_str)
+    }
+
+    /// Pins `fingerprint` for `host`. Returns the previous fingerprint, if any.
+    pub fn insert(&mut self, host: String, fingerprint: String) -> Option<String> {
+        self.hosts.insert(host, fingerprint)
+    }
+
+    /// Removes the fingerprint of `host`. Returns it, if `host` was known.
+    pub fn remove(&mut self, host: &str) -> Option<String> {
+        self.hosts.remove(host)
+    }
+
+    /// Saves the known hosts, which must have been loaded with `lock`.
+    pub fn save(&self) -> Result<()> {
+        if self.lock.is_none() {
+            return Err(anyhow!(
+                "known hosts must be locked before they can be saved"
+            ));
+        }
+
+        let mut contents = String::new();
+        for (host, fingerprint) in &self.hosts {
+            contents.push_str(host);
+            contents.push(' ');
+            contents.push_str(fingerprint);
+            contents.push('\n');
+        }
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| anyhow!("failed to create directory `{}`", dir.display()))?;
+        }
+
+        // Replace the file atomically, so that concurrent clients never see a partial file.
+        let mut tmp_path = OsString::from(&self.path);
+        tmp_path.push(format!(".{}.tmp", process::id()));
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let mut file = File::create(&tmp_path)
+            .with_context(|| anyhow!("failed to create `{}`", tmp_path.display()))?;
+
+        file.write_all(contents.as_bytes())
+            .and_then(|()| file.sync_all())
+            .with_context(|| anyhow!("failed to write `{}`", tmp_path.display()))?;
+
+        fs::rename(&tmp_path, &self.path).with_context(|| {
+            anyhow!(
+                "failed to rename `{}` to `{}`",
+                tmp_path.display(),
+                self.path.display()
+            )
+        })?;
+
+        Ok(())
+    }
+}
+
+fn lock_file(path: &Path) -> io::Result<File> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+
+    rustix::fs::flock(&file, FlockOperation::LockExclusive)?;
+    Ok(file)
+}
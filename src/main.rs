@@ -3,10 +3,13 @@
 mod bus;
 mod cli;
 mod daemon;
+mod delta;
+mod known_hosts;
 mod logging;
 mod schemas;
 mod shutdown_notifier;
 mod task_handle;
+mod tls;
 mod utils;
 
 use anyhow::Result;
@@ -27,11 +30,59 @@ enum Args {
     /// Remove a share.
     Unshare(cli::unshare::Args),
 
+    /// Enable a share.
+    Enable(cli::enable::Args),
+
+    /// Disable a share without removing it.
+    Disable(cli::disable::Args),
+
     /// List all shares of the local daemon.
     List,
 
+    /// Show the URLs on which the local daemon accepts public connections.
+    Addresses,
+
+    /// Show the identity, version and limits of a daemon.
+    Info(cli::info::Args),
+
     /// Query information about a shared file or directory.
     Query(cli::query::Args),
+
+    /// List the contents of a shared directory.
+    Ls(cli::ls::Args),
+
+    /// Search a shared directory recursively.
+    Find(cli::find::Args),
+
+    /// Download a shared file.
+    Get(cli::get::Args),
+
+    /// Print a shared file, optionally following it as it grows.
+    Cat(cli::cat::Args),
+
+    /// Mirror a shared directory into a local directory.
+    Mirror(cli::mirror::Args),
+
+    /// Upload a file into a writable share.
+    Put(cli::put::Args),
+
+    /// Create a directory inside a writable share.
+    Mkdir(cli::mkdir::Args),
+
+    /// Rename or move a file or directory inside a writable share.
+    Mv(cli::mv::Args),
+
+    /// Remove a file or directory from a writable share.
+    Rm(cli::rm::Args),
+
+    /// Print changes to a shared file or directory as they happen.
+    Watch(cli::watch::Args),
+
+    /// Trust the TLS certificate of a daemon.
+    Trust(cli::trust::Args),
+
+    /// Forget the trusted TLS certificate of a daemon.
+    Untrust(cli::untrust::Args),
 }
 
 #[tokio::main]
@@ -43,7 +94,23 @@ async fn main() -> Result<()> {
         Args::ShutDown => cli::shut_down::run().await,
         Args::Share(args) => cli::share::run(args).await,
         Args::Unshare(args) => cli::unshare::run(args).await,
+        Args::Enable(args) => cli::enable::run(args).await,
+        Args::Disable(args) => cli::disable::run(args).await,
         Args::List => cli::list::run().await,
+        Args::Addresses => cli::addresses::run().await,
+        Args::Info(args) => cli::info::run(args).await,
         Args::Query(args) => cli::query::run(args).await,
+        Args::Ls(args) => cli::ls::run(args).await,
+        Args::Find(args) => cli::find::run(args).await,
+        Args::Get(args) => cli::get::run(args).await,
+        Args::Cat(args) => cli::cat::run(args).await,
+        Args::Mirror(args) => cli::mirror::run(args).await,
+        Args::Put(args) => cli::put::run(args).await,
+        Args::Mkdir(args) => cli::mkdir::run(args).await,
+        Args::Mv(args) => cli::mv::run(args).await,
+        Args::Rm(args) => cli::rm::run(args).await,
+        Args::Watch(args) => cli::watch::run(args).await,
+        Args::Trust(args) => cli::trust::run(args).await,
+        Args::Untrust(args) => cli::untrust::run(args).await,
     }
 }
@@ -7,8 +7,76 @@ aldrin::generate! {
 pub use daemon::*;
 pub use wily::*;
 
+/// Version of the Wily protocol, which is bumped whenever functions are added to the `Wily`
+/// service.
+pub const WILY_PROTOCOL: u32 = 3;
+
+/// Oldest version of the Wily protocol that clients can still talk to.
+///
+/// Functions added after this version must be announced in `ServerInfo::features`.
+pub const WILY_MIN_PROTOCOL: u32 = 2;
+
+/// Names of the optional features that a daemon reports in `ServerInfo::features`.
+pub mod features {
+    pub const ARCHIVE: &str = "archive";
+    pub const HASH: &str = "hash";
+    pub const DELTA: &str = "delta";
+    pub const WATCH: &str = "watch";
+    pub const FOLLOW: &str = "follow";
+    pub const SEARCH: &str = "search";
+    pub const IDENTITY: &str = "identity";
+
+    pub const ALL: &[&str] = &[ARCHIVE, HASH, DELTA, WATCH, FOLLOW, SEARCH, IDENTITY];
+}
+
 impl ShareDisabled {
     pub fn any(self) -> bool {
         self.user
     }
 }
+
+impl Default for SymlinkPolicy {
+    fn default() -> Self {
+        Self::FollowInside
+    }
+}
+
+impl Default for ShareAccess {
+    fn default() -> Self {
+        Self::ReadOnly
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+impl Default for HashAlgorithm {
+    fn default() -> Self {
+        Self::Blake3
+    }
+}
+
+impl Default for PatternType {
+    fn default() -> Self {
+        Self::Glob
+    }
+}
+
+impl ServerInfo {
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+}
+
+impl Share {
+    pub fn expires_unix_ms(&self) -> Option<i64> {
+        match self.share_type {
+            ShareType::Static => None,
+            ShareType::Persisted(ref persisted) => persisted.expires_unix_ms,
+            ShareType::Transient(ref transient) => transient.expires_unix_ms,
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::crypto::{self, ring, WebPkiSupportedAlgorithms};
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use tokio_rustls::rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, Error, SignatureScheme,
+};
+use tokio_rustls::TlsConnector;
+use url::Host;
+
+/// Formats the SHA-256 fingerprint of a certificate.
+pub fn fingerprint(cert: &CertificateDer) -> String {
+    let hash = Sha256::digest(cert);
+
+    let hex: Vec<_> = hash.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!("SHA256:{}", hex.join(":"))
+}
+
+/// Parses a fingerprint as formatted by `fingerprint`.
+///
+/// The `SHA256:` prefix and the colons are optional.
+pub fn parse_fingerprint(s: &str) -> Option<String> {
+    let s = s.trim();
+    let hex: String = match s.get(..7) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("SHA256:") => &s[7..],
+        _ => s,
+    }
+    .chars()
+    .filter(|&c| c != ':')
+    .collect();
+
+    if (hex.len() != 64) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let hex = hex.to_ascii_lowercase();
+    let pairs: Vec<_> = hex
+        .as_bytes()
+        .chunks(2)
+        .map(String::from_utf8_lossy)
+        .collect();
+    Some(format!("SHA256:{}", pairs.join(":")))
+}
+
+/// Error of `connect`.
+#[derive(Debug)]
+pub enum ConnectError {
+    /// The daemon's certificate doesn't match the pinned fingerprint.
+    Mismatch {
+        fingerprint: String,
+    },
+
+    Io(io::Error),
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Mismatch { fingerprint } => {
+                write!(f, "unexpected certificate fingerprint {fingerprint}")
+            }
+
+            Self::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl StdError for ConnectError {}
+
+/// Establishes a TLS connection to a daemon over `stream`.
+///
+/// If `pinned` is set, then the daemon's certificate must have that fingerprint. Returns the
+/// fingerprint of the daemon's certificate.
+pub async fn connect(
+    stream: TcpStream,
+    host: &Host<&str>,
+    pinned: Option<&str>,
+) -> Result<(TlsStream<TcpStream>, String), ConnectError> {
+    let server_name = match *host {
+        Host::Domain(domain) => ServerName::try_from(domain.to_owned())
+            .map_err(|e| ConnectError::Io(io::Error::new(io::ErrorKind::InvalidInput, e)))?,
+        Host::Ipv4(ip) => ServerName::from(IpAddr::V4(ip)),
+        Host::Ipv6(ip) => ServerName::from(IpAddr::V6(ip)),
+    };
+
+    let verifier = Arc::new(PinningVerifier::new(pinned));
+
+    let config = ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(verifier.clone())
+        .with_no_client_auth();
+
+    let res = TlsConnector::from(Arc::new(config))
+        .connect(server_name, stream)
+        .await;
+
+    let seen = verifier.seen.lock().take();
+
+    match (res, seen) {
+        (Ok(stream), Some(fingerprint)) => Ok((stream, fingerprint)),
+
+        (Err(_), Some(fingerprint)) if pinned.is_some_and(|pinned| pinned != fingerprint) => {
+            Err(ConnectError::Mismatch { fingerprint })
+        }
+
+        (Err(e), _) => Err(ConnectError::Io(e)),
+
+        (Ok(_), None) => Err(ConnectError::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "daemon didn't present a certificate",
+        ))),
+    }
+}
+
+/// Checks the self-signed certificates of daemons against a pinned fingerprint.
+///
+/// There is no CA that could vouch for a daemon. Instead, the certificate must match the
+/// fingerprint that was seen on first use, and the handshake signatures prove that the peer owns
+/// the certificate's key.
+#[derive(Debug)]
+struct PinningVerifier {
+    algorithms: WebPkiSupportedAlgorithms,
+    pinned: Option<String>,
+    seen: Mutex<Option<String>>,
+}
+
+impl PinningVerifier {
+    fn new(pinned: Option<&str>) -> Self {
+        Self {
+            algorithms: ring::default_provider().signature_verification_algorithms,
+            pinned: pinned.map(ToOwned::to_owned),
+            seen: Mutex::new(None),
+        }
+    }
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer,
+        _intermediates: &[CertificateDer],
+        _server_name: &ServerName,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let fingerprint = fingerprint(end_entity);
+        let matches = match self.pinned {
+            Some(ref pinned) => *pinned == fingerprint,
+            None => true,
+        };
+
+        *self.seen.lock() = Some(fingerprint);
+
+        if matches {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
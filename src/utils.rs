@@ -1,20 +1,39 @@
+use crate::known_hosts::KnownHosts;
 use crate::schemas::{
-    DaemonProxy, Share, ShareType, WilyProxy, DAEMON_OBJECT_UUID, DAEMON_UUID, WILY_OBJECT_UUID,
-    WILY_UUID,
+    DaemonProxy, HashAlgorithm, ServerInfo, Share, ShareAccess, ShareType, SymlinkPolicy,
+    WilyProxy, DAEMON_OBJECT_UUID, DAEMON_UUID, WILY_MIN_PROTOCOL, WILY_OBJECT_UUID, WILY_UUID,
 };
+use crate::tls::{self, ConnectError};
 use aldrin::core::tokio::TokioTransport;
 use aldrin::Client;
 use anyhow::{anyhow, Context, Error, Result};
-use chrono::{Local, TimeZone};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
 use futures::TryFutureExt;
+use percent_encoding::percent_decode_str;
+use sha2::{Digest, Sha256};
 use std::borrow::Cow;
 use std::env;
+use std::fs::File;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::{TcpStream, UnixStream};
 use tokio::task::JoinHandle;
-use url::Url;
+use tokio_rustls::client::TlsStream;
+use url::{Host, Url};
 
-const WILY_PORT: u16 = 9999;
+/// The default port of the public bus, which is used when URLs and listen addresses omit it.
+pub const WILY_PORT: u16 = 9999;
+
+/// The default port of the public bus with TLS.
+pub const WILYS_PORT: u16 = 9998;
+
+/// A connection to a daemon, with or without TLS.
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
 
 pub fn daemon_socket() -> Result<PathBuf> {
     let mut dir = dirs::runtime_dir()
@@ -25,6 +44,14 @@ pub fn daemon_socket() -> Result<PathBuf> {
     Ok(dir)
 }
 
+pub fn data_dir() -> Result<PathBuf> {
+    let mut dir =
+        dirs::data_dir().ok_or_else(|| anyhow!("no directory available for the daemon's data"))?;
+
+    dir.push("wily");
+    Ok(dir)
+}
+
 pub async fn connect_daemon() -> Result<(DaemonProxy, JoinHandle<Result<()>>)> {
     let socket_path = daemon_socket()?;
 
@@ -49,14 +76,38 @@ pub async fn connect_daemon() -> Result<(DaemonProxy, JoinHandle<Result<()>>)> {
     Ok((daemon, join))
 }
 
-pub async fn connect_wily(url: &Url) -> Result<(WilyProxy, JoinHandle<Result<()>>)> {
-    let (host, port) = verify_url(url)?;
+/// Connects to the Wily service at `url`.
+///
+/// Fails with a descriptive error if the peer is too old or lacks any of the optional `features`.
+pub async fn connect_wily(
+    url: &Url,
+    features: &[&str],
+) -> Result<(WilyProxy, JoinHandle<Result<()>>)> {
+    let (wily, _, join) = connect_wily_with_info(url, features).await?;
+    Ok((wily, join))
+}
+
+/// Like `connect_wily`, but also returns the daemon's `ServerInfo`.
+///
+/// This is useful for checking features which are only needed on some code paths.
+pub async fn connect_wily_with_info(
+    url: &Url,
+    features: &[&str],
+) -> Result<(WilyProxy, ServerInfo, JoinHandle<Result<()>>)> {
+    let (host, port, use_tls) = verify_url(url)?;
 
-    let transport = TcpStream::connect((host, port))
+    let stream = connect_tcp(&host, port)
         .await
-        .map(TokioTransport::new)
         .with_context(|| anyhow!("failed to connect to `{host}:{port}`"))?;
 
+    let stream: Box<dyn Stream> = if use_tls {
+        Box::new(connect_pinned(stream, &host, port).await?)
+    } else {
+        Box::new(stream)
+    };
+
+    let transport = TokioTransport::new(stream);
+
     let client = Client::connect(transport)
         .await
         .with_context(|| anyhow!("failed to connect to `{host}:{port}`"))?;
@@ -70,21 +121,192 @@ pub async fn connect_wily(url: &Url) -> Result<(WilyProxy, JoinHandle<Result<()>
         .ok_or_else(|| anyhow!("Wily not found at `{host}:{port}`"))?;
 
     let wily = WilyProxy::new(handle, id).await?;
-    Ok((wily, join))
-}
 
-fn verify_url(url: &Url) -> Result<(&str, u16)> {
-    if url.scheme() != "wily" {
-        return Err(anyhow!("invalid URL scheme `{}`", url.scheme()));
+    // Daemons before protocol 2 don't know `server_info` at all. Any other error is unrelated to
+    // the daemon's version.
+    let info = match wily.server_info().await {
+        Ok(info) => info?,
+
+        Err(aldrin::Error::InvalidFunction { .. }) => {
+            return Err(anyhow!(
+                "the daemon at `{host}:{port}` is too old and must be updated"
+            ));
+        }
+
+        Err(e) => {
+            return Err(e).with_context(|| anyhow!("failed to query `{host}:{port}`"));
+        }
+    };
+
+    if info.protocol < WILY_MIN_PROTOCOL {
+        return Err(anyhow!(
+            "the daemon at `{host}:{port}` is too old (version {}, protocol {}, but at least \
+             protocol {WILY_MIN_PROTOCOL} is required)",
+            info.version,
+            info.protocol,
+        ));
+    }
+
+    if let Some(missing) = features.iter().find(|&&f| !info.supports(f)) {
+        return Err(anyhow!(
+            "the daemon at `{host}:{port}` (version {}) doesn't support `{missing}`",
+            info.version,
+        ));
     }
 
+    Ok((wily, info, join))
+}
+
+/// Checks a `wily://` or `wilys://` URL and returns its host, port and whether it uses TLS.
+pub(crate) fn verify_url(url: &Url) -> Result<(Host<&str>, u16, bool)> {
+    let (default_port, use_tls) = match url.scheme() {
+        "wily" => (WILY_PORT, false),
+        "wilys" => (WILYS_PORT, true),
+        scheme => return Err(anyhow!("invalid URL scheme `{scheme}`")),
+    };
+
+    // `Host` keeps IPv6 addresses unbracketed, unlike `Url::host_str`, but brackets them again
+    // when formatted.
     let host = url
-        .host_str()
+        .host()
         .ok_or_else(|| anyhow!("URL `{url}` doesn't have a host"))?;
 
-    let port = url.port_or_known_default().unwrap_or(WILY_PORT);
+    let port = url.port().unwrap_or(default_port);
+    if port == 0 {
+        return Err(anyhow!("URL `{url}` has an invalid port 0"));
+    }
+
+    Ok((host, port, use_tls))
+}
+
+async fn connect_tcp(host: &Host<&str>, port: u16) -> io::Result<TcpStream> {
+    match *host {
+        Host::Domain(domain) => TcpStream::connect((domain, port)).await,
+        Host::Ipv4(ip) => TcpStream::connect((ip, port)).await,
+        Host::Ipv6(ip) => TcpStream::connect((ip, port)).await,
+    }
+}
+
+/// Establishes a TLS connection and checks the daemon's certificate against the known hosts.
+///
+/// Daemons that aren't known yet are trusted on first use.
+async fn connect_pinned(
+    stream: TcpStream,
+    host: &Host<&str>,
+    port: u16,
+) -> Result<TlsStream<TcpStream>> {
+    let known_host = format!("{host}:{port}");
+    let pinned = KnownHosts::load()?.get(&known_host).map(ToOwned::to_owned);
+
+    match tls::connect(stream, host, pinned.as_deref()).await {
+        Ok((stream, fingerprint)) => {
+            if pinned.is_none() {
+                // Another client may have pinned the host in the meantime.
+                let mut known_hosts = KnownHosts::lock()?;
+
+                match known_hosts.get(&known_host) {
+                    Some(pinned) if pinned == fingerprint => {}
+
+                    Some(pinned) => {
+                        return Err(certificate_changed(&known_host, pinned, &fingerprint));
+                    }
+
+                    None => {
+                        eprintln!(
+                            "Trusting `{known_host}` on first use with certificate fingerprint \
+                             {fingerprint}."
+                        );
+
+                        known_hosts.insert(known_host, fingerprint);
+                        known_hosts.save()?;
+                    }
+                }
+            }
+
+            Ok(stream)
+        }
+
+        Err(ConnectError::Mismatch { fingerprint }) => Err(certificate_changed(
+            &known_host,
+            &pinned.unwrap_or_default(),
+            &fingerprint,
+        )),
 
-    Ok((host, port))
+        Err(e) => Err(e).with_context(|| anyhow!("TLS handshake with `{known_host}` failed")),
+    }
+}
+
+fn certificate_changed(known_host: &str, pinned: &str, fingerprint: &str) -> Error {
+    anyhow!(
+        "the certificate of `{known_host}` has changed from {pinned} to {fingerprint}; this may be \
+         an attack, so only run `wily trust wilys://{known_host}` if the change is expected"
+    )
+}
+
+/// Returns the known hosts entry of a `wilys://` URL.
+pub fn known_host(url: &Url) -> Result<String> {
+    let (host, port, use_tls) = verify_url(url)?;
+
+    if use_tls {
+        Ok(format!("{host}:{port}"))
+    } else {
+        Err(anyhow!("URL `{url}` doesn't use TLS"))
+    }
+}
+
+/// Fetches the certificate fingerprint of the daemon at a `wilys://` URL without checking it.
+pub async fn peer_fingerprint(url: &Url) -> Result<String> {
+    let known_host = known_host(url)?;
+    let (host, port, _) = verify_url(url)?;
+
+    let stream = connect_tcp(&host, port)
+        .await
+        .with_context(|| anyhow!("failed to connect to `{known_host}`"))?;
+
+    let (_, fingerprint) = tls::connect(stream, &host, None)
+        .await
+        .with_context(|| anyhow!("TLS handshake with `{known_host}` failed"))?;
+
+    Ok(fingerprint)
+}
+
+/// Parses a listen address of the public bus.
+///
+/// Both `ip:port` and a plain IP address are accepted; IPv6 addresses must be enclosed in brackets
+/// if a port is given. The port defaults to `WILY_PORT` and `0` picks an ephemeral port.
+pub fn parse_listen_addr(s: &str) -> Result<SocketAddr> {
+    parse_socket_addr(s, WILY_PORT)
+}
+
+/// Parses a TLS listen address of the public bus, like `parse_listen_addr`.
+///
+/// The port defaults to `WILYS_PORT`.
+pub fn parse_tls_listen_addr(s: &str) -> Result<SocketAddr> {
+    parse_socket_addr(s, WILYS_PORT)
+}
+
+fn parse_socket_addr(s: &str, default_port: u16) -> Result<SocketAddr> {
+    let s = s.trim();
+
+    if let Ok(addr) = s.parse() {
+        return Ok(addr);
+    }
+
+    let ip = s
+        .strip_prefix('[')
+        .and_then(|s| s.strip_suffix(']'))
+        .unwrap_or(s);
+
+    ip.parse::<IpAddr>()
+        .map(|ip| SocketAddr::new(ip, default_port))
+        .map_err(|_| anyhow!("invalid listen address `{s}`"))
+}
+
+pub fn url_path(url: &Url) -> Result<String> {
+    percent_decode_str(url.path())
+        .decode_utf8()
+        .map(Cow::into_owned)
+        .with_context(|| anyhow!("URL `{url}` contains an invalid path"))
 }
 
 pub fn ensure_absolute(path: &Path) -> Result<Cow<Path>> {
@@ -99,6 +321,122 @@ pub fn ensure_absolute(path: &Path) -> Result<Cow<Path>> {
     }
 }
 
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let mut total = 0u64;
+    let mut rest = s.trim();
+
+    if rest.is_empty() {
+        return Err(anyhow!("empty duration"));
+    }
+
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(|| anyhow!("missing unit in duration `{s}`"))?;
+
+        let (value, tail) = rest.split_at(digits);
+        let value: u64 = value
+            .parse()
+            .with_context(|| anyhow!("invalid duration `{s}`"))?;
+
+        let unit = tail.chars().next().unwrap();
+        let factor = match unit {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return Err(anyhow!("invalid unit `{unit}` in duration `{s}`")),
+        };
+
+        total = value
+            .checked_mul(factor)
+            .and_then(|value| total.checked_add(value))
+            .ok_or_else(|| anyhow!("duration `{s}` is too long"))?;
+
+        rest = &tail[unit.len_utf8()..];
+    }
+
+    Ok(Duration::from_secs(total))
+}
+
+/// Parses a size in bytes, optionally with a binary unit, e.g. `512`, `4k` or `1.5G`.
+pub fn parse_size(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && (c != '.'))
+        .unwrap_or(s.len());
+
+    let (value, unit) = s.split_at(split);
+    let value: f64 = value
+        .parse()
+        .with_context(|| anyhow!("invalid size `{s}`"))?;
+
+    let factor: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kib" => 1 << 10,
+        "m" | "mib" => 1 << 20,
+        "g" | "gib" => 1 << 30,
+        "t" | "tib" => 1 << 40,
+        _ => return Err(anyhow!("invalid unit `{unit}` in size `{s}`")),
+    };
+
+    Ok((value * factor as f64) as u64)
+}
+
+pub fn parse_datetime(s: &str) -> Result<DateTime<Local>> {
+    const FORMATS: &[&str] = &[
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%dT%H:%M",
+    ];
+
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(s) {
+        return Ok(datetime.with_timezone(&Local));
+    }
+
+    let naive = FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+        .ok_or_else(|| anyhow!("invalid date and time `{s}`"))?;
+
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .ok_or_else(|| anyhow!("`{s}` is not a valid local time"))
+}
+
+/// Computes the hash of a local file.
+///
+/// This blocks and must be called via `tokio::task::spawn_blocking` from async code.
+pub fn hash_file(path: &Path, algorithm: HashAlgorithm) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+
+    match algorithm {
+        HashAlgorithm::Sha256 => {
+            let mut hasher = Sha256::new();
+            io::copy(&mut file, &mut hasher)?;
+            Ok(hasher.finalize().to_vec())
+        }
+
+        HashAlgorithm::Blake3 => {
+            let mut hasher = blake3::Hasher::new();
+            io::copy(&mut file, &mut hasher)?;
+            Ok(hasher.finalize().as_bytes().to_vec())
+        }
+    }
+}
+
+pub fn format_hash(hash: &[u8]) -> String {
+    hash.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
 pub fn print_share(share: &Share) {
     fn print_expires(ts_unix_ms: Option<i64>) {
         print!("Expires:  ");
@@ -134,6 +472,20 @@ pub fn print_share(share: &Share) {
         }
     }
 
+    print!("Access:   ");
+    match share.access {
+        ShareAccess::ReadOnly => println!("read-only"),
+        ShareAccess::ReadWrite => println!("read-write"),
+        ShareAccess::DropBox => println!("drop-box"),
+    }
+
+    print!("Symlinks: ");
+    match share.symlinks {
+        SymlinkPolicy::FollowInside => println!("follow inside"),
+        SymlinkPolicy::Refuse => println!("refuse"),
+        SymlinkPolicy::Expose => println!("expose"),
+    }
+
     print!("Disabled: ");
     if share.disabled.any() {
         if share.disabled.user {